@@ -1,18 +1,29 @@
+mod condition;
+mod error;
+mod expression;
 mod immediate;
 mod instruction;
+mod label;
 mod memory;
 mod mnemonic;
 mod operand;
 mod program;
 mod register;
+mod size;
+pub mod syscalls;
 
+pub use condition::*;
+pub use error::*;
+pub use expression::*;
 pub use immediate::*;
 pub use instruction::*;
+pub use label::*;
 pub use memory::*;
 pub use mnemonic::*;
 pub use operand::*;
 pub use program::*;
 pub use register::*;
+pub use size::*;
 
 pub trait AsAsm {
     fn as_asm(&self) -> String;
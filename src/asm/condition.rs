@@ -0,0 +1,115 @@
+use super::AsAsm;
+
+/// Condition codes, tested against the flags set by a previous instruction such as
+/// [`Cmp`](super::Mnemonic::Cmp).
+///
+/// Used by [`Jcc`], [`Set`] and [`Cmov`]. Unsigned comparisons use *above* and *below* while
+/// signed ones use *greater* and *less*.
+///
+/// # See
+///
+/// - <http://ref.x86asm.net/coder64.html#x0F80>
+/// - <https://en.wikibooks.org/wiki/X86_Assembly/Control_Flow#Comparison_Instructions>
+///
+/// [`Jcc`]: super::Mnemonic::Jcc
+/// [`Set`]: super::Mnemonic::Set
+/// [`Cmov`]: super::Mnemonic::Cmov
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// Overflow
+    O,
+    /// Not overflow
+    No,
+    /// Below (unsigned), carry
+    B,
+    /// Above or equal (unsigned), not carry
+    Ae,
+    /// Equal, zero
+    E,
+    /// Not equal, not zero
+    Ne,
+    /// Below or equal (unsigned)
+    Be,
+    /// Above (unsigned)
+    A,
+    /// Sign (negative)
+    S,
+    /// Not sign (positive or zero)
+    Ns,
+    /// Parity even
+    P,
+    /// Parity odd
+    Np,
+    /// Less (signed)
+    L,
+    /// Greater or equal (signed)
+    Ge,
+    /// Less or equal (signed)
+    Le,
+    /// Greater (signed)
+    G,
+}
+
+impl Condition {
+    /// Carry, alias for [`Condition::B`].
+    pub const C: Self = Self::B;
+    /// Not carry, alias for [`Condition::Ae`].
+    pub const NC: Self = Self::Ae;
+    /// Zero, alias for [`Condition::E`].
+    pub const Z: Self = Self::E;
+    /// Not zero, alias for [`Condition::Ne`].
+    pub const NZ: Self = Self::Ne;
+
+    /// The 4 bits code of this condition, added to the base opcode of conditional instructions.
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The opposite condition, e.g. [`Condition::Ne`] for [`Condition::E`].
+    pub fn negate(&self) -> Self {
+        use Condition::*;
+
+        match self {
+            O => No,
+            No => O,
+            B => Ae,
+            Ae => B,
+            E => Ne,
+            Ne => E,
+            Be => A,
+            A => Be,
+            S => Ns,
+            Ns => S,
+            P => Np,
+            Np => P,
+            L => Ge,
+            Ge => L,
+            Le => G,
+            G => Le,
+        }
+    }
+}
+
+impl AsAsm for Condition {
+    fn as_asm(&self) -> String {
+        match self {
+            Condition::O => "o",
+            Condition::No => "no",
+            Condition::B => "b",
+            Condition::Ae => "ae",
+            Condition::E => "e",
+            Condition::Ne => "ne",
+            Condition::Be => "be",
+            Condition::A => "a",
+            Condition::S => "s",
+            Condition::Ns => "ns",
+            Condition::P => "p",
+            Condition::Np => "np",
+            Condition::L => "l",
+            Condition::Ge => "ge",
+            Condition::Le => "le",
+            Condition::G => "g",
+        }
+        .into()
+    }
+}
@@ -0,0 +1,99 @@
+use std::{error::Error, fmt::Display, ops::Range};
+
+use super::{AsAsm, Size};
+
+/// An error encountered while encoding or backpatching a [`Program`](super::Program).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// A label or constant is referenced but never defined.
+    UndefinedLabel(String),
+    /// A label or constant is defined more than once.
+    DuplicateLabel(String),
+    /// A constant depends on itself, directly or through other constants.
+    CircularConstant(String),
+    /// The operands of an instruction can't be encoded together, e.g. registers of different
+    /// sizes.
+    UnsupportedOperands(String),
+    /// An immediate does not fit in the size of its instruction.
+    ImmediateOutOfRange { value: i64, size: Size },
+    /// A displacement to a label does not fit in its encoding, e.g. a short jump to a far label.
+    DisplacementOverflow { label: String, value: i64 },
+    /// Every duplicate and undefined label of a program, see [`Program::check_labels`].
+    ///
+    /// [`Program::check_labels`]: super::Program::check_labels
+    Labels(Vec<Diagnostic>),
+    /// Every instruction of a program that can't be encoded, see [`Program::check_encoding`].
+    ///
+    /// [`Program::check_encoding`]: super::Program::check_encoding
+    Encoding(Vec<Diagnostic>),
+}
+
+/// A span in the source a program is generated from, e.g. byte offsets in a file.
+pub type Span = Range<usize>;
+
+/// An error located in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub error: AsmError,
+    pub location: Location,
+}
+
+/// Where a label or an instruction is in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// The instruction at this index, with its source span if one was attached, see
+    /// [`Program::span`](super::Program::span).
+    Instruction { index: usize, span: Option<Span> },
+    /// The data entry having this key.
+    Data(String),
+    /// The constant having this name.
+    Constant(String),
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::UndefinedLabel(label) => write!(f, "Label '{label}' not found"),
+            AsmError::DuplicateLabel(label) => write!(f, "Label '{label}' defined more than once"),
+            AsmError::CircularConstant(name) => write!(f, "Constant '{name}' depends on itself"),
+            AsmError::UnsupportedOperands(reason) => write!(f, "Unsupported operands: {reason}"),
+            AsmError::ImmediateOutOfRange { value, size } => {
+                write!(f, "Immediate {value} does not fit in a {}", size.as_asm())
+            }
+            AsmError::DisplacementOverflow { label, value } => {
+                write!(f, "Displacement {value} to label '{label}' overflows")
+            }
+            AsmError::Labels(diagnostics) | AsmError::Encoding(diagnostics) => {
+                let lines: Vec<_> = diagnostics.iter().map(Diagnostic::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.error)
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Instruction { index, span: None } => write!(f, "instruction {index}"),
+            Location::Instruction {
+                index,
+                span: Some(span),
+            } => write!(f, "instruction {index} ({}..{})", span.start, span.end),
+            Location::Data(key) => write!(f, "data '{key}'"),
+            Location::Constant(name) => write!(f, "constant '{name}'"),
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Shorthand for [`AsmError::UnsupportedOperands`].
+pub(crate) fn unsupported<T>(reason: impl Into<String>) -> Result<T, AsmError> {
+    Err(AsmError::UnsupportedOperands(reason.into()))
+}
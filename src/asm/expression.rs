@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    ops::{Add, Sub},
+};
+
+use super::{AsAsm, AsmError, Size};
+
+/// An assemble-time expression made of numbers, labels and constants, e.g. `msg_end - msg`.
+///
+/// Its value is computed during backpatch, and always encoded as a 32 bits immediate.
+///
+/// ```
+/// # use tiny_elf::asm::Expression;
+/// let len = Expression::from("msg_end") - "msg";
+/// let second = Expression::from("msg") + 1;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Expression {
+    term: Term,
+    value: i32,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    /// A label or a constant.
+    Symbol(String),
+    Add(Box<Term>, Box<Term>),
+    Sub(Box<Term>, Box<Term>),
+}
+
+impl Expression {
+    /// The value of this expression, `0` until resolved.
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
+    /// Computes the value of this expression, `symbols` holding the value of every label and
+    /// constant.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::UndefinedLabel`] if a symbol is not in `symbols`, or
+    /// [`AsmError::ImmediateOutOfRange`] if the value does not fit in 32 bits.
+    pub fn resolve(&mut self, symbols: &HashMap<String, i64>) -> Result<(), AsmError> {
+        self.resolve_with(|symbol| symbols.get(symbol).copied())
+    }
+
+    /// Computes the value of this expression, `lookup` giving the value of each symbol.
+    ///
+    /// Useful when symbols depend on a scope, such as local labels.
+    ///
+    /// # Errors
+    ///
+    /// See [`Expression::resolve`].
+    pub fn resolve_with(&mut self, lookup: impl Fn(&str) -> Option<i64>) -> Result<(), AsmError> {
+        let value = self.term.evaluate(&lookup)?;
+
+        self.value = i32::try_from(value).map_err(|_| AsmError::ImmediateOutOfRange {
+            value,
+            size: Size::Dword,
+        })?;
+        Ok(())
+    }
+
+    /// The labels and constants this expression refers to.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols = Vec::new();
+        self.term.symbols(&mut symbols);
+        symbols
+    }
+
+    /// Computes the value of this expression, without checking its size.
+    ///
+    /// See [`Expression::resolve`].
+    pub fn evaluate(&self, symbols: &HashMap<String, i64>) -> Result<i64, AsmError> {
+        self.term.evaluate(&|symbol| symbols.get(symbol).copied())
+    }
+}
+
+impl Term {
+    fn symbols<'a>(&'a self, symbols: &mut Vec<&'a str>) {
+        match self {
+            Term::Number(_) => (),
+            Term::Symbol(s) => symbols.push(s),
+            Term::Add(a, b) | Term::Sub(a, b) => {
+                a.symbols(symbols);
+                b.symbols(symbols);
+            }
+        }
+    }
+
+    fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, AsmError> {
+        Ok(match self {
+            Term::Number(n) => *n,
+            Term::Symbol(s) => lookup(s).ok_or_else(|| AsmError::UndefinedLabel(s.clone()))?,
+            Term::Add(a, b) => a.evaluate(lookup)?.wrapping_add(b.evaluate(lookup)?),
+            Term::Sub(a, b) => a.evaluate(lookup)?.wrapping_sub(b.evaluate(lookup)?),
+        })
+    }
+}
+
+impl<T: Into<Expression>> Add<T> for Expression {
+    type Output = Expression;
+
+    fn add(self, rhs: T) -> Self::Output {
+        Term::Add(Box::new(self.term), Box::new(rhs.into().term)).into()
+    }
+}
+
+impl<T: Into<Expression>> Sub<T> for Expression {
+    type Output = Expression;
+
+    fn sub(self, rhs: T) -> Self::Output {
+        Term::Sub(Box::new(self.term), Box::new(rhs.into().term)).into()
+    }
+}
+
+impl From<Term> for Expression {
+    fn from(term: Term) -> Self {
+        Self { term, value: 0 }
+    }
+}
+
+impl From<&str> for Expression {
+    fn from(symbol: &str) -> Self {
+        Term::Symbol(symbol.into()).into()
+    }
+}
+
+impl From<String> for Expression {
+    fn from(symbol: String) -> Self {
+        Term::Symbol(symbol).into()
+    }
+}
+
+impl From<i32> for Expression {
+    fn from(value: i32) -> Self {
+        Term::Number(value.into()).into()
+    }
+}
+
+impl From<i64> for Expression {
+    fn from(value: i64) -> Self {
+        Term::Number(value).into()
+    }
+}
+
+impl AsAsm for Expression {
+    fn as_asm(&self) -> String {
+        self.term.as_asm()
+    }
+}
+
+impl AsAsm for Term {
+    fn as_asm(&self) -> String {
+        match self {
+            Term::Number(n) => n.to_string(),
+            Term::Symbol(s) => s.clone(),
+            Term::Add(a, b) => format!("{} + {}", a.as_asm(), b.as_asm()),
+            Term::Sub(a, b) => match b.as_ref() {
+                Term::Add(..) | Term::Sub(..) => format!("{} - ({})", a.as_asm(), b.as_asm()),
+                _ => format!("{} - {}", a.as_asm(), b.as_asm()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let mut expr = Expression::from("end") - (Expression::from("start") + 2);
+        assert_eq!(expr.as_asm(), "end - (start + 2)");
+        assert_eq!(expr.symbols(), ["end", "start"]);
+
+        let symbols = HashMap::from([("start".into(), 0x10), ("end".into(), 0x20)]);
+        expr.resolve(&symbols).unwrap();
+        assert_eq!(expr.value(), 14);
+
+        assert_eq!(
+            Expression::from("other").resolve(&symbols),
+            Err(AsmError::UndefinedLabel("other".into()))
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use super::AsAsm;
+use super::{AsAsm, AsmError, Size};
 use crate::prelude::AsBytes;
 
 #[derive(Debug, Clone, Copy)]
@@ -6,6 +6,72 @@ pub enum Immediate {
     Imm8(i8),
     Imm16(i16),
     Imm32(i32),
+    /// Only [`Mov`](super::Mnemonic::Mov) accepts a full 64 bits immediate.
+    Imm64(i64),
+}
+
+impl Immediate {
+    /// Creates the smallest immediate able to hold `value`.
+    ///
+    /// Only 8, 32 and 64 bits immediates are created, as 16 bits ones are never shorter to encode
+    /// for 64 bits operations.
+    pub fn new(value: impl Into<i64>) -> Self {
+        let value = value.into();
+
+        if let Ok(n) = i8::try_from(value) {
+            Immediate::Imm8(n)
+        } else if let Ok(n) = i32::try_from(value) {
+            Immediate::Imm32(n)
+        } else {
+            Immediate::Imm64(value)
+        }
+    }
+
+    /// Converts this immediate to the smallest one holding the same value.
+    ///
+    /// See [`Immediate::new`].
+    pub fn minimal(&self) -> Self {
+        Self::new(self.value())
+    }
+
+    /// The value of this immediate, sign-extended to 64 bits.
+    pub fn value(&self) -> i64 {
+        match self {
+            Immediate::Imm8(n) => *n as i64,
+            Immediate::Imm16(n) => *n as i64,
+            Immediate::Imm32(n) => *n as i64,
+            Immediate::Imm64(n) => *n,
+        }
+    }
+
+    /// Converts this immediate to the one used by an instruction operating on `size`.
+    ///
+    /// Values are accepted both as signed and unsigned, e.g. `255` for a [`Size::Byte`]. As
+    /// instructions sign-extend their 32 bits immediate to 64 bits, a [`Size::Qword`] immediate
+    /// must fit in an `i32`.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::ImmediateOutOfRange`] if the value does not fit in `size`.
+    pub fn for_size(&self, size: Size) -> Result<Self, AsmError> {
+        let value = self.value();
+        let fits = match size {
+            Size::Byte => i8::try_from(value).is_ok() || u8::try_from(value).is_ok(),
+            Size::Word => i16::try_from(value).is_ok() || u16::try_from(value).is_ok(),
+            Size::Dword => i32::try_from(value).is_ok() || u32::try_from(value).is_ok(),
+            Size::Qword => i32::try_from(value).is_ok(),
+        };
+
+        if !fits {
+            return Err(AsmError::ImmediateOutOfRange { value, size });
+        }
+
+        Ok(match size {
+            Size::Byte => Immediate::Imm8(value as i8),
+            Size::Word => Immediate::Imm16(value as i16),
+            Size::Dword | Size::Qword => Immediate::Imm32(value as i32),
+        })
+    }
 }
 
 impl AsBytes for Immediate {
@@ -14,6 +80,7 @@ impl AsBytes for Immediate {
             Immediate::Imm8(n) => n.as_bytes(),
             Immediate::Imm16(n) => n.as_bytes(),
             Immediate::Imm32(n) => n.as_bytes(),
+            Immediate::Imm64(n) => n.as_bytes(),
         }
     }
 }
@@ -22,7 +89,7 @@ macro_rules! impl_from_for_immediate {
     ($ty:ty, $variant:ident) => {
         impl From<$ty> for Immediate {
             fn from(value: $ty) -> Self {
-                Self::$variant(value)
+                Self::$variant(value as _)
             }
         }
     };
@@ -31,6 +98,8 @@ macro_rules! impl_from_for_immediate {
 impl_from_for_immediate!(i8, Imm8);
 impl_from_for_immediate!(i16, Imm16);
 impl_from_for_immediate!(i32, Imm32);
+impl_from_for_immediate!(i64, Imm64);
+impl_from_for_immediate!(u64, Imm64);
 
 impl AsAsm for Immediate {
     fn as_asm(&self) -> String {
@@ -38,6 +107,7 @@ impl AsAsm for Immediate {
             Immediate::Imm8(n) => format!("{n}"),
             Immediate::Imm16(n) => format!("{n}"),
             Immediate::Imm32(n) => format!("{n}"),
+            Immediate::Imm64(n) => format!("{n}"),
         }
     }
 }
@@ -1,53 +1,76 @@
 use crate::prelude::AsBytes;
 use bitflags::bitflags;
 
-use super::{Operand, Register};
+use super::{Memory, Operand, Register, Size};
 
 bitflags! {
+    #[derive(Debug, Clone, Copy)]
     pub struct RexPrefix: u8 {
         /// Enables the use of 64-bit addressing
         const W = 0x48;
         /// Enables the use of extended registers as second operand
         const R = 0x44;
+        /// Enables the use of extended registers as SIB index
+        const X = 0x42;
+        /// Empty REX prefix, needed to encode `spl`, `bpl`, `sil` and `dil`
+        const REX = 0x40;
         /// Enables the use of extended registers as first operand
         const B = 0x41;
     }
 }
 
+/// Legacy prefix selecting 16 bits operands.
+const OPERAND_SIZE_OVERRIDE: u8 = 0x66;
+
 pub struct Instruction {
     prefix: RexPrefix,
+    /// Operand size, `None` for instructions needing no prefix to operate on 64 bits.
+    size: Option<Size>,
     opcode: Vec<u8>,
     operands: Vec<u8>,
     operand_count: u8,
 }
 
 impl Instruction {
+    /// Creates an instruction operating on 64 bits by default.
     pub fn new(opcode: u8) -> Self {
-        Self {
-            prefix: RexPrefix::W,
-            opcode: vec![opcode],
-            operands: Vec::default(),
-            operand_count: 0,
-        }
+        Self::multibyte(vec![opcode])
     }
 
+    /// Creates an instruction with a multibyte opcode, operating on 64 bits by default.
     pub fn multibyte(opcode: Vec<u8>) -> Self {
         Self {
-            prefix: RexPrefix::W,
+            prefix: RexPrefix::empty(),
+            size: Some(Size::Qword),
             opcode,
             operands: Vec::default(),
             operand_count: 0,
         }
     }
 
+    /// Sets the operand size of this instruction.
+    ///
+    /// 64 bits operands need a REX.W prefix while 16 bits ones need the `0x66` prefix.
+    pub fn size(mut self, size: Size) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Removes the operand size of this instruction, for instructions operating on 64 bits
+    /// without REX.W prefix, such as jumps, `push` and `pop`.
+    pub fn default_size(mut self) -> Self {
+        self.size = None;
+        self
+    }
+
     pub fn operand(mut self, operand: Operand) -> Self {
         self.operand_count += 1;
 
-        if matches!(operand, Operand::Reg(r) if r.is_extended()) {
+        if let Operand::Reg(r) = operand {
             if self.operand_count == 1 {
-                self.prefix |= RexPrefix::B;
+                self.register_prefix(r, RexPrefix::B);
             } else if self.operand_count == 2 {
-                self.prefix |= RexPrefix::R;
+                self.register_prefix(r, RexPrefix::R);
             }
         }
 
@@ -56,23 +79,39 @@ impl Instruction {
         self
     }
 
+    /// Defines a register encoded in the last byte of the opcode (e.g. `B8+r`).
+    ///
+    /// # See
+    ///
+    /// - <http://ref.x86asm.net/#column_r>
+    pub fn opcode_register(mut self, reg: Register) -> Self {
+        self.register_prefix(reg, RexPrefix::B);
+
+        if let Some(last) = self.opcode.last_mut() {
+            *last += reg.code();
+        }
+
+        self
+    }
+
     /// Defines an opcode extended register as operand.
     ///
     /// # See
     ///
     /// - <http://ref.x86asm.net/#column_o>
+    ///
+    /// With [`Either::Right`], `reg` is encoded in the ModR/M `reg` field while the other register
+    /// is encoded in the `r/m` field.
     pub fn op_extended_register(mut self, reg: Register, ext: Either<u8, Register>) -> Self {
-        if reg.is_extended() {
-            self.prefix |= RexPrefix::B;
-        }
-
         let mut bytes = match ext {
-            Either::Left(n) => reg.as_bytes_opcode_extend(n),
+            Either::Left(n) => {
+                self.register_prefix(reg, RexPrefix::B);
+                reg.as_bytes_opcode_extend(n)
+            }
             Either::Right(r2) => {
-                if r2.is_extended() {
-                    self.prefix |= RexPrefix::R;
-                }
-                r2.as_bytes_opcode_extend(*reg.as_bytes().first().unwrap())
+                self.register_prefix(reg, RexPrefix::R);
+                self.register_prefix(r2, RexPrefix::B);
+                r2.as_bytes_opcode_extend(reg.code())
             }
         };
 
@@ -80,6 +119,43 @@ impl Instruction {
 
         self
     }
+
+    /// Defines a memory effective address as operand.
+    ///
+    /// `ext` is either an opcode extension or a register, encoded in the ModR/M `reg` field.
+    ///
+    /// # See
+    ///
+    /// - <https://wiki.osdev.org/X86-64_Instruction_Encoding#ModR.2FM_and_SIB_bytes>
+    pub fn op_memory(mut self, mem: &Memory, ext: Either<u8, Register>) -> Self {
+        if mem.is_base_extended() {
+            self.prefix |= RexPrefix::B;
+        }
+        if mem.is_index_extended() {
+            self.prefix |= RexPrefix::X;
+        }
+
+        let reg = match ext {
+            Either::Left(n) => n,
+            Either::Right(r) => {
+                self.register_prefix(r, RexPrefix::R);
+                r.code()
+            }
+        };
+
+        self.operands.append(&mut mem.as_bytes_effective(reg));
+
+        self
+    }
+
+    /// Adds the REX prefix needed to encode `reg`, `flag` being set for extended registers.
+    fn register_prefix(&mut self, reg: Register, flag: RexPrefix) {
+        if reg.is_extended() {
+            self.prefix |= flag;
+        } else if reg.needs_rex() {
+            self.prefix |= RexPrefix::REX;
+        }
+    }
 }
 
 pub enum Either<L, R> {
@@ -89,7 +165,18 @@ pub enum Either<L, R> {
 
 impl AsBytes for Instruction {
     fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![self.prefix.bits()];
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut prefix = self.prefix;
+
+        match self.size {
+            Some(Size::Qword) => prefix |= RexPrefix::W,
+            Some(Size::Word) => bytes.push(OPERAND_SIZE_OVERRIDE),
+            _ => (),
+        }
+
+        if !prefix.is_empty() {
+            bytes.push(prefix.bits());
+        }
 
         bytes.append(&mut self.opcode.clone());
         bytes.append(&mut self.operands.clone());
@@ -0,0 +1,50 @@
+use std::{fmt::Display, ops::Deref};
+
+use super::{Expression, Memory};
+
+/// A label created by [`Program::new_label`](super::Program::new_label), unique within its
+/// program.
+///
+/// It dereferences to its name, so that it can be placed with
+/// [`Program::label`](super::Program::label), and converts to a [`Memory`] or an [`Expression`]
+/// to be referenced.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LabelId(pub(crate) String);
+
+impl Deref for LabelId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for LabelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&LabelId> for Memory {
+    fn from(label: &LabelId) -> Self {
+        label.0.clone().into()
+    }
+}
+
+impl From<LabelId> for Memory {
+    fn from(label: LabelId) -> Self {
+        label.0.into()
+    }
+}
+
+impl From<&LabelId> for Expression {
+    fn from(label: &LabelId) -> Self {
+        label.0.clone().into()
+    }
+}
+
+impl From<LabelId> for Expression {
+    fn from(label: LabelId) -> Self {
+        label.0.into()
+    }
+}
@@ -1,13 +1,154 @@
-use super::AsAsm;
+use super::{unsupported, AsAsm, AsmError, Register, Size};
 use crate::prelude::AsBytes;
 
-#[derive(Debug, Clone)]
+/// A memory reference.
+///
+/// A memory holding only a label is resolved during backpatch, either to a relative address (jumps
+/// and calls, RIP-relative effective addresses) or an absolute one (data addresses).
+///
+/// When used as an effective address (e.g. in [`Lea`]), a memory can also hold a base, a scaled
+/// index and a displacement.
+///
+/// As the source of [`Mov`], [`Push`] or arithmetic instructions, a memory is dereferenced,
+/// unless it is an [address](Memory::is_address) encoded as an immediate.
+///
+/// [`Lea`]: super::Mnemonic::Lea
+/// [`Mov`]: super::Mnemonic::Mov
+/// [`Push`]: super::Mnemonic::Push
+#[derive(Debug, Clone, Default)]
 pub struct Memory {
     addr: i32,
     label: String,
+    base: Option<Register>,
+    index: Option<(Register, Scale)>,
+    disp: i32,
+    /// Size of the data pointed to, when it can't be deduced from the other operand.
+    size: Option<Size>,
+    /// Encoded as a rel8 jump target, see [`Memory::is_short`].
+    short: bool,
+    /// Never encoded as a rel8 jump target, see [`Memory::near`].
+    near: bool,
+}
+
+/// Scale factor of an index register.
+#[derive(Debug, Clone, Copy)]
+pub enum Scale {
+    One,
+    Two,
+    Four,
+    Eight,
 }
 
 impl Memory {
+    /// Creates an effective address based on a register, e.g. `[rax]`.
+    ///
+    /// Only 64 bits registers can be used as base, see [`Memory::check_registers`].
+    pub fn base(base: Register) -> Self {
+        Self {
+            base: Some(base),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a scaled index register to this address, e.g. `[rax + rbx*4]`.
+    ///
+    /// [`Rsp`](Register::Rsp) cannot be used as an index, and only 64 bits registers can be used,
+    /// see [`Memory::check_registers`].
+    pub fn index(mut self, index: Register, scale: Scale) -> Self {
+        self.index = Some((index, scale));
+        self
+    }
+
+    /// Adds a displacement to this address, e.g. `[rax + 8]` or `msg + 4`.
+    pub fn disp(mut self, disp: i32) -> Self {
+        self.disp = disp;
+        self
+    }
+
+    /// The displacement of this address, see [`Memory::disp`].
+    pub fn displacement(&self) -> i32 {
+        self.disp
+    }
+
+    /// Sets the size of the data pointed to by this address, e.g. `byte [rsi]`.
+    ///
+    /// Needed when the instruction can't deduce it from its other operand, such as
+    /// [`Movzx`](super::Mnemonic::Movzx).
+    pub fn with_size(mut self, size: Size) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// The size of the data pointed to by this address, if set.
+    ///
+    /// See [`Memory::with_size`].
+    pub fn size(&self) -> Option<Size> {
+        self.size
+    }
+
+    /// The base and index registers of this address.
+    pub fn registers(&self) -> Vec<Register> {
+        self.base
+            .into_iter()
+            .chain(self.index.map(|(index, _)| index))
+            .collect()
+    }
+
+    /// Checks that the base and index registers of this address can be encoded.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::UnsupportedOperands`] if a register isn't a 64 bits one, or if the index is
+    /// [`Rsp`](Register::Rsp).
+    pub fn check_registers(&self) -> Result<(), AsmError> {
+        if self.registers().iter().any(|r| r.size() != Size::Qword) {
+            return unsupported("only 64 bits registers can be used as base or index");
+        }
+        if matches!(self.index, Some((Register::Rsp, _))) {
+            return unsupported("rsp cannot be used as an index register");
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the base or index register `from` by `to`.
+    pub fn replace_register(&mut self, from: Register, to: Register) {
+        if self.base == Some(from) {
+            self.base = Some(to);
+        }
+        if let Some((index, _)) = &mut self.index {
+            if *index == from {
+                *index = to;
+            }
+        }
+    }
+
+    /// Forces this jump target to be encoded with a 32 bits displacement, even if the branch
+    /// relaxation could use a shorter one.
+    pub fn near(mut self) -> Self {
+        self.near = true;
+        self.short = false;
+        self
+    }
+
+    /// Determines if this jump target is forced to a 32 bits displacement.
+    ///
+    /// See [`Memory::near`].
+    pub fn is_near(&self) -> bool {
+        self.near
+    }
+
+    /// Determines if this jump target is encoded with an 8 bits displacement.
+    pub fn is_short(&self) -> bool {
+        self.short
+    }
+
+    /// Sets this jump target to be encoded with an 8 bits displacement, unless it is forced
+    /// [`near`](Memory::near).
+    pub fn set_short(&mut self, short: bool) {
+        self.short = short && !self.near;
+    }
+
     pub fn label(&self) -> &str {
         self.label.as_ref()
     }
@@ -15,35 +156,174 @@ impl Memory {
     pub fn set_addr(&mut self, addr: i32) {
         self.addr = addr;
     }
+
+    /// The resolved address of this memory, displacement included.
+    pub fn addr(&self) -> i32 {
+        self.addr.wrapping_add(self.disp)
+    }
+
+    /// Determines if this memory is an address rather than a reference to the data it points to,
+    /// which is the case of a label without size, e.g. `msg` in `mov rsi, msg`.
+    ///
+    /// Setting a size (see [`Memory::with_size`]) dereferences it, e.g. `mov rax, qword [rel x]`.
+    pub fn is_address(&self) -> bool {
+        self.is_rip_relative() && self.size.is_none()
+    }
+
+    /// Determines if this memory is only made of a label.
+    ///
+    /// Such a memory is encoded RIP-relative when used as an effective address.
+    pub fn is_rip_relative(&self) -> bool {
+        !self.label.is_empty() && self.base.is_none() && self.index.is_none()
+    }
+
+    /// Encodes this memory as an effective address, with `reg` being the value of the ModR/M
+    /// `reg` field (either a register or an opcode extension).
+    ///
+    /// Returns the ModR/M byte, followed by the optional SIB byte and displacement.
+    ///
+    /// # See
+    ///
+    /// - <https://wiki.osdev.org/X86-64_Instruction_Encoding#ModR.2FM_and_SIB_bytes>
+    pub fn as_bytes_effective(&self, reg: u8) -> Vec<u8> {
+        let reg = (reg & 0b111) << 3;
+        let disp = self.addr();
+
+        if self.is_rip_relative() {
+            let mut bytes = vec![reg | 0b101];
+            bytes.append(&mut disp.as_bytes());
+            return bytes;
+        }
+
+        let (mode, mut disp_bytes) = match self.base {
+            // no base always needs a 32 bits displacement
+            None => (0b00, disp.as_bytes()),
+            // rbp and r13 as base can't be encoded without displacement
+            Some(b) if disp == 0 && self.label.is_empty() && b.code() != 0b101 => (0b00, vec![]),
+            Some(_) if self.label.is_empty() && i8::try_from(disp).is_ok() => {
+                (0b01, (disp as i8).as_bytes())
+            }
+            Some(_) => (0b10, disp.as_bytes()),
+        };
+
+        let mut bytes = match (self.base, self.index) {
+            (Some(b), None) if b.code() != 0b100 => vec![mode << 6 | reg | b.code()],
+            (base, index) => {
+                let (index, scale) = index
+                    .map(|(i, s)| (i.code(), s as u8))
+                    .unwrap_or((0b100, 0));
+                let base = base.map(|b| b.code()).unwrap_or(0b101);
+
+                vec![mode << 6 | reg | 0b100, scale << 6 | index << 3 | base]
+            }
+        };
+
+        bytes.append(&mut disp_bytes);
+        bytes
+    }
+
+    /// Determines if the base register of this memory is an extended register.
+    pub fn is_base_extended(&self) -> bool {
+        self.base.is_some_and(|b| b.is_extended())
+    }
+
+    /// Determines if the index register of this memory is an extended register.
+    pub fn is_index_extended(&self) -> bool {
+        self.index.is_some_and(|(i, _)| i.is_extended())
+    }
+
+    /// Formats this memory as an effective address, e.g. `[rax + rbx*4 + 8]`.
+    ///
+    /// The address is prefixed by its size if set, e.g. `byte [rsi]`.
+    pub fn as_asm_effective(&self) -> String {
+        match self.size {
+            Some(size) => format!("{} {}", size.as_asm(), self.as_asm_address()),
+            None => self.as_asm_address(),
+        }
+    }
+
+    fn as_asm_address(&self) -> String {
+        if self.is_rip_relative() {
+            return format!("[rel {}]", self.with_disp_asm(self.label.clone()));
+        }
+
+        let mut parts = Vec::new();
+        if !self.label.is_empty() {
+            parts.push(self.label.clone());
+        }
+        if let Some(base) = self.base {
+            parts.push(base.as_asm());
+        }
+        if let Some((index, scale)) = self.index {
+            parts.push(format!("{}*{}", index.as_asm(), scale.as_asm()));
+        }
+        if parts.is_empty() {
+            parts.push(self.disp.to_string());
+            return format!("[{}]", parts.join(" + "));
+        }
+
+        format!("[{}]", self.with_disp_asm(parts.join(" + ")))
+    }
+
+    fn with_disp_asm(&self, expr: String) -> String {
+        match self.disp {
+            0 => expr,
+            d if d < 0 => format!("{expr} - {}", d.unsigned_abs()),
+            d => format!("{expr} + {d}"),
+        }
+    }
 }
 
 impl From<&str> for Memory {
     fn from(value: &str) -> Self {
         Self {
-            addr: 0,
             label: value.into(),
+            ..Default::default()
         }
     }
 }
 
 impl From<String> for Memory {
     fn from(label: String) -> Self {
-        Self { addr: 0, label }
+        Self {
+            label,
+            ..Default::default()
+        }
     }
 }
 
 impl AsBytes for Memory {
     fn as_bytes(&self) -> Vec<u8> {
-        self.addr.as_bytes()
+        let addr = self.addr();
+
+        if self.short {
+            (addr as i8).as_bytes()
+        } else {
+            addr.as_bytes()
+        }
     }
 }
 
 impl AsAsm for Memory {
     fn as_asm(&self) -> String {
         if self.label.is_empty() {
-            format!("{}", self.addr)
+            format!("{}", self.addr())
+        } else if self.near {
+            format!("near {}", self.with_disp_asm(self.label.clone()))
         } else {
-            self.label.clone()
+            self.with_disp_asm(self.label.clone())
+        }
+    }
+}
+
+impl AsAsm for Scale {
+    fn as_asm(&self) -> String {
+        match self {
+            Scale::One => "1",
+            Scale::Two => "2",
+            Scale::Four => "4",
+            Scale::Eight => "8",
         }
+        .into()
     }
 }
@@ -1,21 +1,54 @@
 use super::{
-    register::Register, AsAsm, Either, Immediate::*, Instruction, Memory, Operand, RexPrefix,
+    register::Register, unsupported, AsAsm, AsmError, Condition, Either, Immediate::*, Instruction,
+    Memory, Operand, Size,
 };
 use crate::prelude::AsBytes;
 
-const EXPECT_ONE_BYTE_REGISTER: &str = "Registers are always 1 byte";
-
 /// # See
 ///
 /// <http://ref.x86asm.net/coder64-abc.html>
 /// <https://wiki.osdev.org/X86-64_Instruction_Encoding#ModR.2FM>
 #[derive(Debug, Clone)]
 pub enum Mnemonic {
+    /// Add with carry, useful for multi-word additions.
+    Adc(Register, Operand),
     Add(Register, Operand),
+    /// Pads the code with NOPs up to a multiple of the first value, e.g. `align 16`.
+    ///
+    /// The second value is the padding length, computed by the [`Program`](super::Program).
+    Align(u32, u32),
     Call(Memory),
+    /// Calls the address held by a 64 bits register or stored in memory, e.g. `call rax` or
+    /// `call [rel handler]`.
+    CallIndirect(Operand),
+    /// Sign-extends `eax` into `edx:eax`.
+    Cdq,
+    /// Conditional move, only accepts a register or a memory as source.
+    Cmov(Condition, Register, Operand),
     Cmp(Register, Operand),
+    /// A comment in the output assembly, e.g. `; write`, encoded as nothing.
+    Comment(String),
+    /// Sign-extends [`Rax`] into [`Rdx`]:[`Rax`], usually before an [`IDiv`](Mnemonic::IDiv).
+    ///
+    /// [`Rax`]: Register::Rax
+    /// [`Rdx`]: Register::Rdx
+    Cqo,
+    /// Sign-extends `ax` into `dx:ax`.
+    Cwd,
     Dec(Register),
-    /// Dividend needs to be in [`Rax`] before calling `IDiv`.
+    /// Unsigned division of [`Rdx`]:[`Rax`] by the register.
+    ///
+    /// [`Rdx`] needs to be zeroed for a 64 bits dividend in [`Rax`].
+    ///
+    /// Remainder is stored in [`Rdx`] while quotient is stored in [`Rax`].
+    ///
+    /// [`Rax`]: Register::Rax
+    /// [`Rdx`]: Register::Rdx
+    Div(Register),
+    /// Signed division of [`Rdx`]:[`Rax`] by the register.
+    ///
+    /// Dividend needs to be in [`Rax`] and sign-extended to [`Rdx`] with [`Cqo`](Mnemonic::Cqo)
+    /// before calling `IDiv`.
     ///
     /// Remainder is stored in [`Rdx`] while quotient is stored in [`Rax`].
     ///
@@ -26,230 +59,765 @@ pub enum Mnemonic {
     IDiv(Register),
     Inc(Register),
     IMul(Register, Operand),
+    /// Conditional jump.
+    Jcc(Condition, Memory),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::E`].
     Je(Memory),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::G`].
     Jg(Memory),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::Ge`].
     Jge(Memory),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::L`].
     Jl(Memory),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::Le`].
     Jle(Memory),
     Jmp(Memory),
+    /// Jumps to the address held by a 64 bits register or stored in memory, e.g. `jmp rax` or
+    /// `jmp [table + rax*8]`.
+    JmpIndirect(Operand),
+    /// Shorthand for [`Jcc`](Mnemonic::Jcc) with [`Condition::Ne`].
     Jne(Memory),
     Label(String),
+    /// Loads the effective address of a memory in a register.
+    ///
+    /// A memory made of a single label is encoded RIP-relative.
+    Lea(Register, Memory),
+    /// Moves a register, an immediate or a memory to a register.
+    ///
+    /// An [address](Memory::is_address) is moved as an immediate, e.g. `mov rsi, msg`.
     Mov(Register, Operand),
+    /// Sign-extending move of an 8 or 16 bits register or memory.
+    ///
+    /// The size of a memory must be set, see [`Memory::with_size`].
+    Movsx(Register, Operand),
+    /// Sign-extending move of a 32 bits register or memory to a 64 bits register.
+    Movsxd(Register, Operand),
+    /// Zero-extending move of an 8 or 16 bits register or memory.
+    ///
+    /// The size of a memory must be set, see [`Memory::with_size`].
+    Movzx(Register, Operand),
+    /// Unsigned multiplication of [`Rax`] by the register.
+    ///
+    /// The 128 bits result is stored in [`Rdx`]:[`Rax`].
+    ///
+    /// [`Rax`]: Register::Rax
+    /// [`Rdx`]: Register::Rdx
+    Mul(Register),
     Pop(Register),
+    /// Pushes a 64 bits register or memory, or an immediate sign-extended to 64 bits.
     Push(Operand),
     /// Alias for RETN
     Ret,
+    /// Sets the 8 bits register to 1 if the condition is met, 0 otherwise.
+    Set(Condition, Register),
+    /// Subtract with borrow, useful for multi-word subtractions.
+    Sbb(Register, Operand),
+    /// Moves a register or an immediate to memory, e.g. `mov [rdi], al`.
+    ///
+    /// The size of the memory must be set to store an immediate, see [`Memory::with_size`].
+    Store(Memory, Operand),
     Sub(Register, Operand),
     Syscall,
     Xor(Register, Operand),
 }
 
-impl AsBytes for Mnemonic {
-    fn as_bytes(&self) -> Vec<u8> {
+impl Mnemonic {
+    /// Replaces every immediate of this instruction by the smallest one holding the same value,
+    /// so that the shortest encoding is chosen.
+    ///
+    /// See [`Immediate::minimal`](super::Immediate::minimal).
+    pub fn minimize_immediates(&mut self) {
+        if let Some(Operand::Imm(imm)) = self.immediate_operand_mut() {
+            *imm = imm.minimal();
+        }
+    }
+
+    /// The operand of this instruction able to hold an immediate or an [`Expression`], if any.
+    ///
+    /// [`Expression`]: super::Expression
+    pub fn immediate_operand_mut(&mut self) -> Option<&mut Operand> {
         match self {
-            Mnemonic::Add(r, op) => {
-                match op {
-                    Operand::Mem(_) => unimplemented!(),
-                    Operand::Imm(imm) => {
-                        match imm {
-                            // http://ref.x86asm.net/coder64.html#x83
-                            Imm8(_) => Instruction::new(0x83),
-                            // http://ref.x86asm.net/coder64.html#x81
-                            Imm16(_) | Imm32(_) => Instruction::new(0x81),
-                        }
-                        .operand((*r).into())
-                        .operand((*imm).into())
-                        .as_bytes()
-                    }
-                    // http://ref.x86asm.net/coder64.html#x03
-                    Operand::Reg(r2) => Instruction::new(0x03)
-                        .op_extended_register(*r, Either::Right(*r2))
-                        .as_bytes(),
-                }
+            Mnemonic::Adc(_, o)
+            | Mnemonic::Add(_, o)
+            | Mnemonic::Cmp(_, o)
+            | Mnemonic::IMul(_, o)
+            | Mnemonic::Mov(_, o)
+            | Mnemonic::Push(o)
+            | Mnemonic::Sbb(_, o)
+            | Mnemonic::Store(_, o)
+            | Mnemonic::Sub(_, o)
+            | Mnemonic::Xor(_, o) => Some(o),
+            _ => None,
+        }
+    }
+
+    /// The memory referenced by this instruction, if any, jump and call targets excluded.
+    ///
+    /// See [`Mnemonic::branch_target_mut`] for those.
+    pub fn memory_operand_mut(&mut self) -> Option<&mut Memory> {
+        match self {
+            Mnemonic::Lea(_, mem) | Mnemonic::Store(mem, _) => Some(mem),
+            Mnemonic::Adc(_, Operand::Mem(mem))
+            | Mnemonic::Add(_, Operand::Mem(mem))
+            | Mnemonic::CallIndirect(Operand::Mem(mem))
+            | Mnemonic::Cmov(_, _, Operand::Mem(mem))
+            | Mnemonic::Cmp(_, Operand::Mem(mem))
+            | Mnemonic::IMul(_, Operand::Mem(mem))
+            | Mnemonic::JmpIndirect(Operand::Mem(mem))
+            | Mnemonic::Mov(_, Operand::Mem(mem))
+            | Mnemonic::Movsx(_, Operand::Mem(mem))
+            | Mnemonic::Movsxd(_, Operand::Mem(mem))
+            | Mnemonic::Movzx(_, Operand::Mem(mem))
+            | Mnemonic::Push(Operand::Mem(mem))
+            | Mnemonic::Sbb(_, Operand::Mem(mem))
+            | Mnemonic::Sub(_, Operand::Mem(mem))
+            | Mnemonic::Xor(_, Operand::Mem(mem)) => Some(mem),
+            _ => None,
+        }
+    }
+
+    /// Determines if the memory of this instruction is an [address](Memory::is_address) encoded
+    /// as an immediate, rather than an effective address.
+    pub fn has_address_operand(&self) -> bool {
+        matches!(
+            self.clone().immediate_operand_mut(),
+            Some(Operand::Mem(mem)) if mem.is_address()
+        )
+    }
+
+    /// The register written by this instruction as its destination operand, if any.
+    ///
+    /// Registers implicitly written, e.g. [`Rax`](super::Register::Rax) and
+    /// [`Rdx`](super::Register::Rdx) by a division, are not included.
+    pub fn destination(&self) -> Option<Register> {
+        match self {
+            Mnemonic::Adc(r, _)
+            | Mnemonic::Add(r, _)
+            | Mnemonic::Cmov(_, r, _)
+            | Mnemonic::Dec(r)
+            | Mnemonic::Inc(r)
+            | Mnemonic::IMul(r, _)
+            | Mnemonic::Lea(r, _)
+            | Mnemonic::Mov(r, _)
+            | Mnemonic::Movsx(r, _)
+            | Mnemonic::Movsxd(r, _)
+            | Mnemonic::Movzx(r, _)
+            | Mnemonic::Pop(r)
+            | Mnemonic::Set(_, r)
+            | Mnemonic::Sbb(r, _)
+            | Mnemonic::Sub(r, _)
+            | Mnemonic::Xor(r, _) => Some(*r),
+            _ => None,
+        }
+    }
+
+    /// The target of this instruction if it is a direct jump or call, encoded relative to the next
+    /// instruction.
+    pub fn branch_target_mut(&mut self) -> Option<&mut Memory> {
+        match self {
+            Mnemonic::Call(a) => Some(a),
+            _ => self.relaxable_target_mut(),
+        }
+    }
+
+    /// The target of this instruction if it is a jump having a short (rel8) encoding.
+    pub fn relaxable_target_mut(&mut self) -> Option<&mut Memory> {
+        match self {
+            Mnemonic::Jcc(_, a)
+            | Mnemonic::Je(a)
+            | Mnemonic::Jg(a)
+            | Mnemonic::Jge(a)
+            | Mnemonic::Jl(a)
+            | Mnemonic::Jle(a)
+            | Mnemonic::Jmp(a)
+            | Mnemonic::Jne(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a jump to `target`, using the `short` rel8 opcode if the target is short, or the
+/// `near` rel32 opcode otherwise.
+///
+/// # Errors
+///
+/// [`AsmError::DisplacementOverflow`] if a short target is out of the rel8 range.
+fn jump(target: &Memory, short: u8, near: Vec<u8>) -> Result<Vec<u8>, AsmError> {
+    // the displacement encoded, see `Memory::as_bytes`
+    if target.is_short() && i8::try_from(target.addr()).is_err() {
+        return Err(AsmError::DisplacementOverflow {
+            label: target.label().into(),
+            value: target.addr().into(),
+        });
+    }
+
+    Ok(if target.is_short() {
+        Instruction::new(short)
+    } else {
+        Instruction::multibyte(near)
+    }
+    .default_size()
+    .operand(target.to_owned().into())
+    .as_bytes())
+}
+
+/// Encodes a conditional jump to `target`.
+fn jcc(cc: Condition, target: &Memory) -> Result<Vec<u8>, AsmError> {
+    // http://ref.x86asm.net/coder64.html#x70
+    // http://ref.x86asm.net/coder64.html#x0F80
+    jump(target, 0x70 + cc.code(), vec![0x0F, 0x80 + cc.code()])
+}
+
+/// Encodes the arithmetic and logic instructions sharing the same opcodes layout, `ext` being
+/// their opcode extension (e.g. `0` for `add`, `7` for `cmp`).
+///
+/// # See
+///
+/// - <http://ref.x86asm.net/coder64.html#x80>
+fn alu(ext: u8, r: &Register, op: &Operand) -> Result<Vec<u8>, AsmError> {
+    let size = r.size();
+
+    Ok(match op {
+        // `r8, r/m8` and `r, r/m` forms, e.g. http://ref.x86asm.net/coder64.html#x02
+        Operand::Mem(mem) => {
+            memory_size(r, mem)?;
+            Instruction::new(ext * 8 + if size == Size::Byte { 0x02 } else { 0x03 })
+                .size(size)
+                .op_memory(mem, Either::Right(*r))
+                .as_bytes()
+        }
+        Operand::Expr(_) => unreachable!("expressions are encoded as immediates"),
+        Operand::Imm(imm) => match (size, imm) {
+            // http://ref.x86asm.net/coder64.html#x80
+            (Size::Byte, _) => Instruction::new(0x80)
+                .size(size)
+                .op_extended_register(*r, Either::Left(ext))
+                .operand(imm.for_size(size)?.into()),
+            // http://ref.x86asm.net/coder64.html#x83
+            (_, Imm8(_)) => Instruction::new(0x83)
+                .size(size)
+                .op_extended_register(*r, Either::Left(ext))
+                .operand((*imm).into()),
+            // http://ref.x86asm.net/coder64.html#x81
+            _ => Instruction::new(0x81)
+                .size(size)
+                .op_extended_register(*r, Either::Left(ext))
+                .operand(imm.for_size(size)?.into()),
+        }
+        .as_bytes(),
+        // `r8, r/m8` and `r, r/m` forms, e.g. http://ref.x86asm.net/coder64.html#x02
+        Operand::Reg(r2) => {
+            same_size(r, r2)?;
+            Instruction::new(ext * 8 + if size == Size::Byte { 0x02 } else { 0x03 })
+                .size(size)
+                .op_extended_register(*r, Either::Right(*r2))
+                .as_bytes()
+        }
+    })
+}
+
+/// Encodes `len` bytes of NOPs, using the recommended multi-byte NOPs to limit their count.
+///
+/// # See
+///
+/// - <https://www.felixcloutier.com/x86/nop>
+fn nops(len: usize) -> Vec<u8> {
+    const NOPS: [&[u8]; 9] = [
+        &[0x90],
+        &[0x66, 0x90],
+        &[0x0F, 0x1F, 0x00],
+        &[0x0F, 0x1F, 0x40, 0x00],
+        &[0x0F, 0x1F, 0x44, 0x00, 0x00],
+        &[0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00],
+        &[0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00],
+        &[0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    ];
+
+    let mut bytes = Vec::with_capacity(len);
+    while bytes.len() < len {
+        let nop = NOPS[(len - bytes.len()).min(NOPS.len()) - 1];
+        bytes.extend_from_slice(nop);
+    }
+
+    bytes
+}
+
+/// Encodes one operand instructions having a `r/m8` opcode and a `r/m` one.
+fn unary(opcode8: u8, opcode: u8, ext: u8, r: &Register) -> Result<Vec<u8>, AsmError> {
+    let size = r.size();
+
+    Ok(
+        Instruction::new(if size == Size::Byte { opcode8 } else { opcode })
+            .size(size)
+            .op_extended_register(*r, Either::Left(ext))
+            .as_bytes(),
+    )
+}
+
+/// # Errors
+///
+/// If the size of the memory is set and differs from the register one.
+fn memory_size(r: &Register, mem: &Memory) -> Result<(), AsmError> {
+    match mem.size() {
+        Some(size) if size != r.size() => unsupported(format!(
+            "operand size mismatch between {} and {}",
+            r.as_asm(),
+            mem.as_asm_effective()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// # Errors
+///
+/// If both registers don't have the same size.
+fn same_size(r: &Register, r2: &Register) -> Result<(), AsmError> {
+    if r.size() != r2.size() {
+        return unsupported(format!(
+            "operand size mismatch between {} and {}",
+            r.as_asm(),
+            r2.as_asm()
+        ));
+    }
+    Ok(())
+}
+
+/// # Errors
+///
+/// If the register is a [`Size::Byte`] one, which `mnemonic` does not support.
+fn not_byte(mnemonic: &str, r: &Register) -> Result<Size, AsmError> {
+    if r.size() == Size::Byte {
+        return unsupported(format!(
+            "{mnemonic} does not support 8 bits register {}",
+            r.as_asm()
+        ));
+    }
+    Ok(r.size())
+}
+
+impl Mnemonic {
+    /// Encodes this instruction.
+    ///
+    /// # Errors
+    ///
+    /// - [`AsmError::UnsupportedOperands`] if the operands can't be encoded together, e.g.
+    ///   registers of different sizes.
+    /// - [`AsmError::ImmediateOutOfRange`] if an immediate does not fit in the operation size.
+    /// - [`AsmError::DisplacementOverflow`] if a short jump target is too far.
+    pub fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        if let Some(mem) = self.clone().memory_operand_mut() {
+            mem.check_registers()?;
+        }
+
+        // expressions and addresses are encoded as 32 bits immediates, whatever their value, so
+        // that the size of the instruction doesn't change once resolved
+        let value = match self.clone().immediate_operand_mut() {
+            Some(Operand::Expr(e)) => Some(e.value()),
+            Some(Operand::Mem(mem)) if mem.is_address() => Some(mem.addr()),
+            _ => None,
+        };
+        if let Some(value) = value {
+            let mut inst = self.clone();
+            if let Some(o) = inst.immediate_operand_mut() {
+                *o = Imm32(value).into();
+            }
+            return inst.encode();
+        }
+
+        Ok(match self {
+            Mnemonic::Adc(r, op) => alu(2, r, op)?,
+            Mnemonic::Add(r, op) => alu(0, r, op)?,
+            Mnemonic::Align(align, _) if !align.is_power_of_two() => {
+                return unsupported(format!("alignment {align} is not a power of two"));
             }
+            Mnemonic::Align(_, padding) => nops(*padding as usize),
             // http://ref.x86asm.net/coder64.html#xE8
             Mnemonic::Call(mem) => Instruction::new(0xE8)
+                .default_size()
                 .operand(mem.to_owned().into())
                 .as_bytes(),
-            Mnemonic::Cmp(r, o) => match o {
-                // http://ref.x86asm.net/coder64.html#x81_7
-                Operand::Imm(i) => Instruction::new(0x81)
-                    .op_extended_register(*r, Either::Left(7))
-                    .operand((*i).into())
-                    .as_bytes(),
-                // http://ref.x86asm.net/coder64.html#x39
-                Operand::Reg(r2) => Instruction::new(0x39)
-                    .op_extended_register(*r2, Either::Right(*r))
-                    .as_bytes(),
-                Operand::Mem(_) => unimplemented!(),
-            },
+            // http://ref.x86asm.net/coder64.html#xFF_2
+            Mnemonic::CallIndirect(o) => indirect(2, o)?,
+            // http://ref.x86asm.net/coder64.html#x99
+            Mnemonic::Cdq => vec![0x99],
+            Mnemonic::Cmov(cc, r, o) => {
+                // http://ref.x86asm.net/coder64.html#x0F40
+                let inst =
+                    Instruction::multibyte(vec![0x0F, 0x40 + cc.code()]).size(not_byte("cmov", r)?);
+
+                match o {
+                    Operand::Reg(r2) => {
+                        same_size(r, r2)?;
+                        inst.op_extended_register(*r, Either::Right(*r2))
+                    }
+                    Operand::Mem(mem) => inst.op_memory(mem, Either::Right(*r)),
+                    Operand::Imm(_) | Operand::Expr(_) => {
+                        return unsupported("cmov needs a register or a memory source")
+                    }
+                }
+                .as_bytes()
+            }
+            Mnemonic::Cmp(r, o) => alu(7, r, o)?,
+            Mnemonic::Comment(_) => vec![],
+            // http://ref.x86asm.net/coder64.html#x99
+            Mnemonic::Cqo => Instruction::new(0x99).as_bytes(),
+            // http://ref.x86asm.net/coder64.html#x99
+            Mnemonic::Cwd => Instruction::new(0x99).size(Size::Word).as_bytes(),
+            // http://ref.x86asm.net/coder64.html#xFE_1
             // http://ref.x86asm.net/coder64.html#xFF_1
-            Mnemonic::Dec(r) => Instruction::new(0xFF)
-                .op_extended_register(*r, Either::Left(1))
-                .as_bytes(),
+            Mnemonic::Dec(r) => unary(0xFE, 0xFF, 1, r)?,
+            // http://ref.x86asm.net/coder64.html#xF6_6
+            // http://ref.x86asm.net/coder64.html#xF7_6
+            Mnemonic::Div(r) => unary(0xF6, 0xF7, 6, r)?,
+            // http://ref.x86asm.net/coder64.html#xFE_0
             // http://ref.x86asm.net/coder64.html#xFF_0
-            Mnemonic::Inc(r) => Instruction::new(0xFF)
-                .op_extended_register(*r, Either::Left(0))
-                .as_bytes(),
+            Mnemonic::Inc(r) => unary(0xFE, 0xFF, 0, r)?,
+            // http://ref.x86asm.net/coder64.html#xF6_7
             // http://ref.x86asm.net/coder64.html#xF7_7
-            Mnemonic::IDiv(r) => Instruction::new(0xF7)
-                .op_extended_register(*r, Either::Left(7))
-                .as_bytes(),
+            Mnemonic::IDiv(r) => unary(0xF6, 0xF7, 7, r)?,
             Mnemonic::IMul(r, op) => {
+                let size = not_byte("imul", r)?;
+
                 match op {
-                    Operand::Mem(_) => unimplemented!(),
-                    Operand::Imm(imm) => {
-                        match imm {
-                            // http://ref.x86asm.net/coder64.html#x6B
-                            Imm8(_) => Instruction::new(0x6B),
-                            // http://ref.x86asm.net/coder64.html#x69
-                            Imm16(_) | Imm32(_) => Instruction::new(0x69),
-                        }
-                        .op_extended_register(*r, Either::Right(*r))
-                        .operand((*imm).into())
-                        .as_bytes()
+                    // http://ref.x86asm.net/coder64.html#x0FAF
+                    Operand::Mem(mem) => {
+                        memory_size(r, mem)?;
+                        Instruction::multibyte(vec![0x0F, 0xAF])
+                            .size(size)
+                            .op_memory(mem, Either::Right(*r))
+                            .as_bytes()
                     }
+                    Operand::Expr(_) => unreachable!("expressions are encoded as immediates"),
+                    Operand::Imm(imm) => match imm {
+                        // http://ref.x86asm.net/coder64.html#x6B
+                        Imm8(_) => Instruction::new(0x6B)
+                            .size(size)
+                            .op_extended_register(*r, Either::Right(*r))
+                            .operand((*imm).into()),
+                        // http://ref.x86asm.net/coder64.html#x69
+                        _ => Instruction::new(0x69)
+                            .size(size)
+                            .op_extended_register(*r, Either::Right(*r))
+                            .operand(imm.for_size(size)?.into()),
+                    }
+                    .as_bytes(),
                     // http://ref.x86asm.net/coder64.html#x0FAF
-                    Operand::Reg(r2) => Instruction::multibyte(vec![0x0F, 0xAF])
-                        .op_extended_register(*r, Either::Right(*r2))
-                        .as_bytes(),
+                    Operand::Reg(r2) => {
+                        same_size(r, r2)?;
+                        Instruction::multibyte(vec![0x0F, 0xAF])
+                            .size(size)
+                            .op_extended_register(*r, Either::Right(*r2))
+                            .as_bytes()
+                    }
                 }
             }
-            // http://ref.x86asm.net/coder64.html#x0F84
-            Mnemonic::Je(a) => Instruction::multibyte(vec![0x0F, 0x84])
-                .operand(a.to_owned().into())
-                .as_bytes(),
-            // http://ref.x86asm.net/coder64.html#x0F8D
-            Mnemonic::Jge(a) => Instruction::multibyte(vec![0x0F, 0x8D])
-                .operand(a.to_owned().into())
-                .as_bytes(),
-            // http://ref.x86asm.net/coder64.html#x0F8F
-            Mnemonic::Jg(a) => Instruction::multibyte(vec![0x0F, 0x8F])
-                .operand(a.to_owned().into())
-                .as_bytes(),
-            // http://ref.x86asm.net/coder64.html#x0F8C
-            Mnemonic::Jl(a) => Instruction::multibyte(vec![0x0F, 0x8C])
-                .operand(a.to_owned().into())
-                .as_bytes(),
-            // http://ref.x86asm.net/coder64.html#x0F8E
-            Mnemonic::Jle(a) => Instruction::multibyte(vec![0x0F, 0x8E])
-                .operand(a.to_owned().into())
-                .as_bytes(),
+            Mnemonic::Jcc(cc, a) => jcc(*cc, a)?,
+            Mnemonic::Je(a) => jcc(Condition::E, a)?,
+            Mnemonic::Jge(a) => jcc(Condition::Ge, a)?,
+            Mnemonic::Jg(a) => jcc(Condition::G, a)?,
+            Mnemonic::Jl(a) => jcc(Condition::L, a)?,
+            Mnemonic::Jle(a) => jcc(Condition::Le, a)?,
             // http://ref.x86asm.net/coder64.html#xE9
-            Mnemonic::Jmp(mem) => Instruction::new(0xE9)
-                .operand(mem.to_owned().into())
-                .as_bytes(),
-            // http://ref.x86asm.net/coder64.html#x0F85
-            Mnemonic::Jne(mem) => Instruction::multibyte(vec![0x0F, 0x85])
-                .operand(mem.to_owned().into())
-                .as_bytes(),
+            // http://ref.x86asm.net/coder64.html#xEB
+            Mnemonic::Jmp(mem) => jump(mem, 0xEB, vec![0xE9])?,
+            // http://ref.x86asm.net/coder64.html#xFF_4
+            Mnemonic::JmpIndirect(o) => indirect(4, o)?,
+            Mnemonic::Jne(mem) => jcc(Condition::Ne, mem)?,
             Mnemonic::Label(_) => vec![],
-            Mnemonic::Mov(r, o) => match o {
-                // http://ref.x86asm.net/coder64.html#x8B
-                Operand::Reg(r2) => {
-                    let mut prefix = RexPrefix::W.bits();
-                    if r.is_extended() {
-                        prefix |= RexPrefix::B.bits();
+            // http://ref.x86asm.net/coder64.html#x8D
+            Mnemonic::Lea(r, mem) => Instruction::new(0x8D)
+                .size(not_byte("lea", r)?)
+                .op_memory(mem, Either::Right(*r))
+                .as_bytes(),
+            Mnemonic::Mov(r, o) => {
+                let size = r.size();
+
+                match (size, o) {
+                    // http://ref.x86asm.net/coder64.html#x88
+                    // http://ref.x86asm.net/coder64.html#x89
+                    (_, Operand::Reg(r2)) => {
+                        same_size(r, r2)?;
+                        // for some reason move register are reversed
+                        Instruction::new(if size == Size::Byte { 0x88 } else { 0x89 })
+                            .size(size)
+                            .op_extended_register(*r2, Either::Right(*r))
                     }
-                    if r2.is_extended() {
-                        prefix |= RexPrefix::R.bits();
+                    // http://ref.x86asm.net/coder64.html#x8A
+                    // http://ref.x86asm.net/coder64.html#x8B
+                    (_, Operand::Mem(mem)) => {
+                        memory_size(r, mem)?;
+                        Instruction::new(if size == Size::Byte { 0x8A } else { 0x8B })
+                            .size(size)
+                            .op_memory(mem, Either::Right(*r))
                     }
-                    let mut inst = vec![prefix, 0x89];
-                    // for some reason move register are reversed
-                    inst.append(&mut r.as_bytes_opcode_extend(
-                        *r2.as_bytes().first().expect(EXPECT_ONE_BYTE_REGISTER),
-                    ));
-                    inst
+                    (_, Operand::Expr(_)) => unreachable!("expressions are encoded as immediates"),
+                    // http://ref.x86asm.net/coder64.html#xB8
+                    (Size::Qword, Operand::Imm(Imm64(_))) => Instruction::new(0xB8)
+                        .opcode_register(*r)
+                        .operand(o.to_owned()),
+                    // smaller immediates are sign-extended from 32 bits
+                    // http://ref.x86asm.net/coder64.html#xC7
+                    (Size::Qword, Operand::Imm(imm)) => Instruction::new(0xC7)
+                        .operand((*r).into())
+                        .operand(imm.for_size(size)?.into()),
+                    // http://ref.x86asm.net/coder64.html#xB0
+                    (Size::Byte, Operand::Imm(imm)) => Instruction::new(0xB0)
+                        .size(size)
+                        .opcode_register(*r)
+                        .operand(imm.for_size(size)?.into()),
+                    // 32 bits moves zero-extend the register to 64 bits
+                    // http://ref.x86asm.net/coder64.html#xB8
+                    (_, Operand::Imm(imm)) => Instruction::new(0xB8)
+                        .size(size)
+                        .opcode_register(*r)
+                        .operand(imm.for_size(size)?.into()),
                 }
-                // http://ref.x86asm.net/coder64.html#xC7
-                Operand::Mem(_) | Operand::Imm(Imm16(_) | Imm32(_)) => Instruction::new(0xC7)
-                    .operand((*r).into())
-                    .operand(o.to_owned())
-                    .as_bytes(),
-                Operand::Imm(Imm8(_)) => unimplemented!(),
-            },
-            // http://ref.x86asm.net/coder64.html#x8F
-            Mnemonic::Pop(r) => Instruction::new(0x8F).operand((*r).into()).as_bytes(),
+                .as_bytes()
+            }
+            // http://ref.x86asm.net/coder64.html#x0FBE
+            // http://ref.x86asm.net/coder64.html#x0FBF
+            Mnemonic::Movsx(r, o) => extend("movsx", 0xBE, r, o)?,
+            // http://ref.x86asm.net/coder64.html#x63
+            Mnemonic::Movsxd(r, o) => {
+                if r.size() != Size::Qword {
+                    return unsupported("movsxd needs a 64 bits destination register");
+                }
+
+                let inst = Instruction::new(0x63);
+                match o {
+                    Operand::Reg(r2) if r2.size() == Size::Dword => {
+                        inst.op_extended_register(*r, Either::Right(*r2))
+                    }
+                    Operand::Mem(mem) if matches!(mem.size(), None | Some(Size::Dword)) => {
+                        inst.op_memory(mem, Either::Right(*r))
+                    }
+                    _ => return unsupported("movsxd needs a 32 bits source"),
+                }
+                .as_bytes()
+            }
+            // http://ref.x86asm.net/coder64.html#x0FB6
+            // http://ref.x86asm.net/coder64.html#x0FB7
+            Mnemonic::Movzx(r, o) => extend("movzx", 0xB6, r, o)?,
+            // http://ref.x86asm.net/coder64.html#xF6_4
+            // http://ref.x86asm.net/coder64.html#xF7_4
+            Mnemonic::Mul(r) => unary(0xF6, 0xF7, 4, r)?,
+            // http://ref.x86asm.net/coder64.html#x58
+            Mnemonic::Pop(r) => stack_register(0x58, r)?,
             Mnemonic::Push(o) => match o {
+                // http://ref.x86asm.net/coder64.html#x50
+                Operand::Reg(r) => stack_register(0x50, r)?,
+                Operand::Imm(i) => match i {
+                    // http://ref.x86asm.net/coder64.html#x6A
+                    Imm8(_) => Instruction::new(0x6A).operand((*i).into()),
+                    // http://ref.x86asm.net/coder64.html#x68
+                    _ => Instruction::new(0x68).operand(i.for_size(Size::Qword)?.into()),
+                }
+                .default_size()
+                .as_bytes(),
                 // http://ref.x86asm.net/coder64.html#xFF_6
-                Operand::Reg(r) => Instruction::new(0xFF)
-                    .op_extended_register(*r, Either::Left(6))
-                    .as_bytes(),
-                Operand::Imm(i) => {
-                    match i {
-                        // http://ref.x86asm.net/coder64.html#x6A
-                        Imm8(_) => Instruction::new(0x6A),
-                        // http://ref.x86asm.net/coder64.html#x68
-                        Imm16(_) | Imm32(_) => Instruction::new(0x68),
+                Operand::Mem(mem) => {
+                    if mem.size().is_some_and(|s| s != Size::Qword) {
+                        return unsupported("only 64 bits memories can be pushed");
                     }
-                    .operand((*i).into())
-                    .as_bytes()
+
+                    Instruction::new(0xFF)
+                        .default_size()
+                        .op_memory(mem, Either::Left(6))
+                        .as_bytes()
                 }
-                Operand::Mem(_) => unimplemented!(),
+                Operand::Expr(_) => unreachable!("expressions are encoded as immediates"),
             },
             // http://ref.x86asm.net/coder64.html#xC3
-            Mnemonic::Ret => Instruction::new(0xC3).as_bytes(),
-            Mnemonic::Sub(r, op) => {
-                match op {
-                    Operand::Mem(_) => unimplemented!(),
-                    Operand::Imm(imm) => {
-                        match imm {
-                            // http://ref.x86asm.net/coder64.html#x83_5
-                            Imm8(_) => Instruction::new(0x83),
-                            // http://ref.x86asm.net/coder64.html#x81_5
-                            Imm16(_) | Imm32(_) => Instruction::new(0x81),
-                        }
-                        .op_extended_register(*r, Either::Left(5))
-                        .operand((*imm).into())
+            Mnemonic::Ret => vec![0xC3],
+            Mnemonic::Sbb(r, op) => alu(3, r, op)?,
+            Mnemonic::Store(mem, o) => match o {
+                // http://ref.x86asm.net/coder64.html#x88
+                // http://ref.x86asm.net/coder64.html#x89
+                Operand::Reg(r) => {
+                    memory_size(r, mem)?;
+                    Instruction::new(if r.size() == Size::Byte { 0x88 } else { 0x89 })
+                        .size(r.size())
+                        .op_memory(mem, Either::Right(*r))
                         .as_bytes()
-                    }
-                    // http://ref.x86asm.net/coder64.html#x2B
-                    Operand::Reg(r2) => Instruction::new(0x2B)
-                        .op_extended_register(*r, Either::Right(*r2))
-                        .as_bytes(),
                 }
+                // http://ref.x86asm.net/coder64.html#xC6
+                // http://ref.x86asm.net/coder64.html#xC7
+                Operand::Imm(imm) => {
+                    let Some(size) = mem.size() else {
+                        return unsupported(
+                            "the size of the memory must be set to store an immediate",
+                        );
+                    };
+
+                    Instruction::new(if size == Size::Byte { 0xC6 } else { 0xC7 })
+                        .size(size)
+                        .op_memory(mem, Either::Left(0))
+                        .operand(imm.for_size(size)?.into())
+                        .as_bytes()
+                }
+                Operand::Mem(_) => return unsupported("cannot move a memory to another memory"),
+                Operand::Expr(_) => unreachable!("expressions are encoded as immediates"),
+            },
+            // http://ref.x86asm.net/coder64.html#x0F90
+            Mnemonic::Set(cc, r) => {
+                if r.size() != Size::Byte {
+                    return unsupported(format!("set{} needs an 8 bits register", cc.as_asm()));
+                }
+
+                Instruction::multibyte(vec![0x0F, 0x90 + cc.code()])
+                    .size(Size::Byte)
+                    .op_extended_register(*r, Either::Left(0))
+                    .as_bytes()
             }
+            Mnemonic::Sub(r, op) => alu(5, r, op)?,
             // http://ref.x86asm.net/coder64.html#x0F05
             Mnemonic::Syscall => vec![0x0f, 0x05],
-            Mnemonic::Xor(r, op) => match op {
-                Operand::Imm(_) | Operand::Mem(_) => unimplemented!(),
-                Operand::Reg(r2) => Instruction::new(0x33)
-                    .op_extended_register(*r, Either::Right(*r2))
-                    .as_bytes(),
-            },
+            Mnemonic::Xor(r, op) => alu(6, r, op)?,
+        })
+    }
+}
+
+impl AsBytes for Mnemonic {
+    /// Encodes this instruction, see [`Mnemonic::encode`].
+    ///
+    /// An instruction that can't be encoded gives no bytes, as needed to lay out a program before
+    /// reporting errors. [`Program`](super::Program) backpatch reports it, with its location,
+    /// before computing any offset, see [`Program::check_encoding`](super::Program::check_encoding).
+    /// Use [`Mnemonic::encode`] or [`Program::encode`](super::Program::encode) to get the error.
+    fn as_bytes(&self) -> Vec<u8> {
+        self.encode().unwrap_or_default()
+    }
+}
+
+/// Encodes indirect `call` and `jmp`, `ext` being their opcode extension.
+///
+/// # Errors
+///
+/// If the operand isn't a 64 bits register or a memory.
+fn indirect(ext: u8, o: &Operand) -> Result<Vec<u8>, AsmError> {
+    let inst = Instruction::new(0xFF).default_size();
+
+    Ok(match o {
+        Operand::Reg(r) if r.size() == Size::Qword => {
+            inst.op_extended_register(*r, Either::Left(ext))
         }
+        Operand::Mem(mem) => inst.op_memory(mem, Either::Left(ext)),
+        _ => return unsupported("indirect branches need a 64 bits register or a memory"),
     }
+    .as_bytes())
+}
+
+/// Encodes `movzx` and `movsx`, `opcode` being the second byte of their 8 bits source form.
+///
+/// # Errors
+///
+/// If the source isn't an 8 or 16 bits register or memory, or if it isn't smaller than the
+/// destination.
+fn extend(mnemonic: &str, opcode: u8, r: &Register, o: &Operand) -> Result<Vec<u8>, AsmError> {
+    let src_size = match o {
+        Operand::Reg(r2) => Some(r2.size()),
+        Operand::Mem(mem) => mem.size(),
+        Operand::Imm(_) | Operand::Expr(_) => None,
+    };
+
+    let opcode = match src_size {
+        Some(Size::Byte) if r.size() != Size::Byte => opcode,
+        Some(Size::Word) if matches!(r.size(), Size::Dword | Size::Qword) => opcode + 1,
+        _ => {
+            return unsupported(format!(
+                "{mnemonic} needs an 8 or 16 bits source smaller than {}",
+                r.as_asm()
+            ))
+        }
+    };
+
+    let inst = Instruction::multibyte(vec![0x0F, opcode]).size(r.size());
+    Ok(match o {
+        Operand::Reg(r2) => inst.op_extended_register(*r, Either::Right(*r2)),
+        Operand::Mem(mem) => inst.op_memory(mem, Either::Right(*r)),
+        Operand::Imm(_) | Operand::Expr(_) => unreachable!(),
+    }
+    .as_bytes())
+}
+
+/// Encodes `push` and `pop` of a register, only available on 64 and 16 bits.
+fn stack_register(opcode: u8, r: &Register) -> Result<Vec<u8>, AsmError> {
+    Ok(match r.size() {
+        Size::Qword => Instruction::new(opcode).default_size(),
+        Size::Word => Instruction::new(opcode).size(Size::Word),
+        _ => return unsupported("only 64 and 16 bits registers can be pushed or popped"),
+    }
+    .opcode_register(*r)
+    .as_bytes())
 }
 
 impl AsAsm for Mnemonic {
     fn as_asm(&self) -> String {
         match self {
-            Mnemonic::Add(r, v) => format!("add {}, {}", r.as_asm(), v.as_asm()),
+            Mnemonic::Adc(r, v) => format!("adc {}, {}", r.as_asm(), source(v)),
+            Mnemonic::Add(r, v) => format!("add {}, {}", r.as_asm(), source(v)),
+            Mnemonic::Align(boundary, _) => format!("align {boundary}"),
             Mnemonic::Call(mem) => format!("call {}", mem.as_asm()),
-            Mnemonic::Cmp(r, o) => format!("cmp {}, {}", r.as_asm(), o.as_asm()),
+            Mnemonic::CallIndirect(o) => format!("call {}", effective(o)),
+            Mnemonic::Cdq => "cdq".into(),
+            Mnemonic::Cmov(cc, r, o) => {
+                format!("cmov{} {}, {}", cc.as_asm(), r.as_asm(), effective(o))
+            }
+            Mnemonic::Cmp(r, o) => format!("cmp {}, {}", r.as_asm(), source(o)),
+            Mnemonic::Comment(text) => format!("; {text}"),
+            Mnemonic::Cqo => "cqo".into(),
+            Mnemonic::Cwd => "cwd".into(),
             Mnemonic::Dec(r) => format!("dec {}", r.as_asm()),
+            Mnemonic::Div(r) => format!("div {}", r.as_asm()),
             Mnemonic::Inc(r) => format!("inc {}", r.as_asm()),
             Mnemonic::IDiv(r) => format!("idiv {}", r.as_asm()),
-            Mnemonic::IMul(r, imm) => format!("imul {}, {}", r.as_asm(), imm.as_asm()),
+            Mnemonic::IMul(r, imm) => format!("imul {}, {}", r.as_asm(), source(imm)),
+            Mnemonic::Jcc(cc, a) => format!("j{} {}", cc.as_asm(), a.as_asm()),
             Mnemonic::Je(a) => format!("je {}", a.as_asm()),
             Mnemonic::Jg(a) => format!("jg {}", a.as_asm()),
             Mnemonic::Jge(a) => format!("jge {}", a.as_asm()),
             Mnemonic::Jl(a) => format!("jl {}", a.as_asm()),
             Mnemonic::Jle(a) => format!("jle {}", a.as_asm()),
             Mnemonic::Jmp(a) => format!("jmp {}", a.as_asm()),
+            Mnemonic::JmpIndirect(o) => format!("jmp {}", effective(o)),
             Mnemonic::Jne(a) => format!("jne {}", a.as_asm()),
             Mnemonic::Label(l) => format!("\n{l}:"),
-            Mnemonic::Mov(r, o) => format!("mov {}, {}", r.as_asm(), o.as_asm()),
+            Mnemonic::Lea(r, mem) => format!("lea {}, {}", r.as_asm(), mem.as_asm_effective()),
+            Mnemonic::Mov(r, o) => format!("mov {}, {}", r.as_asm(), source(o)),
+            Mnemonic::Movsx(r, o) => format!("movsx {}, {}", r.as_asm(), effective(o)),
+            Mnemonic::Movsxd(r, o) => format!("movsxd {}, {}", r.as_asm(), effective(o)),
+            Mnemonic::Movzx(r, o) => format!("movzx {}, {}", r.as_asm(), effective(o)),
+            Mnemonic::Mul(r) => format!("mul {}", r.as_asm()),
             Mnemonic::Pop(r) => format!("pop {}", r.as_asm()),
-            Mnemonic::Push(o) => format!("push {}", o.as_asm()),
+            Mnemonic::Push(o) => format!("push {}", source(o)),
             Mnemonic::Ret => "ret".into(),
-            Mnemonic::Sub(r, v) => format!("sub {}, {}", r.as_asm(), v.as_asm()),
+            Mnemonic::Set(cc, r) => format!("set{} {}", cc.as_asm(), r.as_asm()),
+            Mnemonic::Sbb(r, v) => format!("sbb {}, {}", r.as_asm(), source(v)),
+            Mnemonic::Store(mem, o) => format!("mov {}, {}", mem.as_asm_effective(), o.as_asm()),
+            Mnemonic::Sub(r, v) => format!("sub {}, {}", r.as_asm(), source(v)),
             Mnemonic::Syscall => "syscall".into(),
-            Mnemonic::Xor(r, o) => format!("xor {}, {}", r.as_asm(), o.as_asm()),
+            Mnemonic::Xor(r, o) => format!("xor {}, {}", r.as_asm(), source(o)),
         }
     }
 }
 
+/// Formats an operand whose memory is dereferenced unless it is an [address](Memory::is_address).
+fn source(o: &Operand) -> String {
+    match o {
+        Operand::Mem(mem) if !mem.is_address() => mem.as_asm_effective(),
+        o => o.as_asm(),
+    }
+}
+
+/// Formats an operand whose memory is always an effective address, never a label address.
+fn effective(o: &Operand) -> String {
+    match o {
+        Operand::Mem(mem) => mem.as_asm_effective(),
+        o => o.as_asm(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Mnemonic::*;
@@ -308,5 +876,436 @@ mod tests {
                 ]
             );
         }
+
+        #[test]
+        fn imm64() {
+            let bytes = Mov(R9, 0x1122334455667788i64.into()).as_bytes();
+            assert_eq!(
+                bytes,
+                vec![0x49, 0xB9, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]
+            );
+            assert_eq!(
+                Mov(Rax, u64::MAX.into()).as_asm(),
+                "mov rax, -1",
+                "nasm accepts negative 64 bits immediates"
+            );
+        }
+
+        #[test]
+        fn imm8_sign_extended() {
+            let bytes = Mov(Rax, (-1i8).into()).as_bytes();
+            assert_eq!(bytes, vec![0x48, 0xC7, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        }
+    }
+
+    mod sub_registers {
+        use super::*;
+
+        #[test]
+        fn mov() {
+            assert_eq!(Mov(Eax, 1.into()).as_bytes(), vec![0xB8, 1, 0, 0, 0]);
+            assert_eq!(Mov(Ax, 1.into()).as_bytes(), vec![0x66, 0xB8, 1, 0]);
+            assert_eq!(Mov(Al, 200.into()).as_bytes(), vec![0xB0, 0xC8]);
+            assert_eq!(Mov(Sil, 1.into()).as_bytes(), vec![0x40, 0xB6, 1]);
+            assert_eq!(Mov(R8b, 1.into()).as_bytes(), vec![0x41, 0xB0, 1]);
+            assert_eq!(Mov(R8d, Ecx.into()).as_bytes(), vec![0x41, 0x89, 0xC8]);
+            assert_eq!(Mov(Ax, Bx.into()).as_bytes(), vec![0x66, 0x89, 0xD8]);
+            assert_eq!(Mov(Dil, Al.into()).as_bytes(), vec![0x40, 0x88, 0xC7]);
+        }
+
+        #[test]
+        fn arithmetic() {
+            assert_eq!(Add(Al, 5.into()).as_bytes(), vec![0x80, 0xC0, 0x05]);
+            assert_eq!(
+                Add(Ax, 1000.into()).as_bytes(),
+                vec![0x66, 0x81, 0xC0, 0xE8, 0x03]
+            );
+            assert_eq!(
+                Add(R9w, 5i8.into()).as_bytes(),
+                vec![0x66, 0x41, 0x83, 0xC1, 0x05]
+            );
+            assert_eq!(Cmp(Bl, Cl.into()).as_bytes(), vec![0x3A, 0xD9]);
+            assert_eq!(Xor(Eax, Eax.into()).as_bytes(), vec![0x33, 0xC0]);
+            assert_eq!(Inc(R8b).as_bytes(), vec![0x41, 0xFE, 0xC0]);
+            assert_eq!(IDiv(R8d).as_bytes(), vec![0x41, 0xF7, 0xF8]);
+        }
+
+        #[test]
+        fn stack() {
+            assert_eq!(Push(Rbp.into()).as_bytes(), vec![0x55]);
+            assert_eq!(Push(R12.into()).as_bytes(), vec![0x41, 0x54]);
+            assert_eq!(Pop(R12).as_bytes(), vec![0x41, 0x5C]);
+            assert_eq!(Push(Ax.into()).as_bytes(), vec![0x66, 0x50]);
+        }
+
+        #[test]
+        fn size_mismatch() {
+            assert!(matches!(
+                Mov(Rax, Ebx.into()).encode(),
+                Err(AsmError::UnsupportedOperands(_))
+            ));
+            assert!(Mov(Rax, Ebx.into()).as_bytes().is_empty());
+        }
+
+        #[test]
+        fn with_size() {
+            assert_eq!(R9.with_size(Size::Byte), R9b);
+            assert_eq!(Dil.with_size(Size::Qword), Rdi);
+            assert_eq!(Esp.size(), Size::Dword);
+            assert_eq!(R15w.as_asm(), "r15w");
+        }
+    }
+
+    mod extend {
+        use super::*;
+        use crate::asm::{Memory, Scale};
+
+        #[test]
+        fn movzx() {
+            let byte = |r| Memory::base(r).with_size(Size::Byte);
+            assert_eq!(
+                Movzx(Eax, byte(Rsi).into()).as_bytes(),
+                vec![0x0F, 0xB6, 0x06]
+            );
+            assert_eq!(
+                Movzx(Rax, byte(Rsi).index(Rcx, Scale::One).into()).as_bytes(),
+                vec![0x48, 0x0F, 0xB6, 0x04, 0x0E]
+            );
+            assert_eq!(Movzx(Eax, Cl.into()).as_bytes(), vec![0x0F, 0xB6, 0xC1]);
+            assert_eq!(
+                Movzx(R8, Memory::base(Rbx).with_size(Size::Word).into()).as_bytes(),
+                vec![0x4C, 0x0F, 0xB7, 0x03]
+            );
+            assert_eq!(
+                Movzx(Ax, Bl.into()).as_bytes(),
+                vec![0x66, 0x0F, 0xB6, 0xC3]
+            );
+            assert_eq!(
+                Movzx(Eax, byte(Rsi).into()).as_asm(),
+                "movzx eax, byte [rsi]"
+            );
+        }
+
+        #[test]
+        fn movsx() {
+            assert_eq!(
+                Movsx(Eax, Sil.into()).as_bytes(),
+                vec![0x40, 0x0F, 0xBE, 0xC6]
+            );
+            assert_eq!(
+                Movsx(R8d, Memory::base(Rbp).disp(-2).with_size(Size::Word).into()).as_bytes(),
+                vec![0x44, 0x0F, 0xBF, 0x45, 0xFE]
+            );
+            assert_eq!(
+                Movsxd(Rax, Memory::base(Rsi).into()).as_bytes(),
+                vec![0x48, 0x63, 0x06]
+            );
+            assert_eq!(Movsxd(R9, Eax.into()).as_bytes(), vec![0x4C, 0x63, 0xC8]);
+        }
+
+        #[test]
+        fn movzx_unsized_memory() {
+            assert!(matches!(
+                Movzx(Eax, Memory::base(Rsi).into()).encode(),
+                Err(AsmError::UnsupportedOperands(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn indirect() {
+        use crate::asm::{Memory, Scale};
+
+        assert_eq!(CallIndirect(Rax.into()).as_bytes(), vec![0xFF, 0xD0]);
+        assert_eq!(CallIndirect(R11.into()).as_bytes(), vec![0x41, 0xFF, 0xD3]);
+        assert_eq!(JmpIndirect(Rax.into()).as_bytes(), vec![0xFF, 0xE0]);
+        assert_eq!(
+            JmpIndirect(Memory::base(R12).disp(8).into()).as_bytes(),
+            vec![0x41, 0xFF, 0x64, 0x24, 0x08]
+        );
+
+        let mut table = Memory::from("table").index(Rcx, Scale::Eight);
+        table.set_addr(0x400000);
+        assert_eq!(
+            JmpIndirect(table.clone().into()).as_bytes(),
+            vec![0xFF, 0x24, 0xCD, 0x00, 0x00, 0x40, 0x00]
+        );
+        assert_eq!(JmpIndirect(table.into()).as_asm(), "jmp [table + rcx*8]");
+    }
+
+    #[test]
+    fn cmp_imm8() {
+        assert_eq!(
+            Cmp(Rax, 5i8.into()).as_bytes(),
+            vec![0x48, 0x83, 0xF8, 0x05]
+        );
+    }
+
+    #[test]
+    fn extended_register_operands() {
+        // the destination is in the ModR/M reg field, extended by REX.R, the source in the r/m
+        // field, extended by REX.B
+        assert_eq!(Add(R9, Rax.into()).as_bytes(), vec![0x4C, 0x03, 0xC8]);
+        assert_eq!(Add(Rax, R9.into()).as_bytes(), vec![0x49, 0x03, 0xC1]);
+        assert_eq!(
+            IMul(R10, R11.into()).as_bytes(),
+            vec![0x4D, 0x0F, 0xAF, 0xD3]
+        );
+    }
+
+    #[test]
+    fn minimal_immediates() {
+        let mut add = Add(Rax, 1.into());
+        assert_eq!(add.as_bytes().len(), 7);
+
+        add.minimize_immediates();
+        assert_eq!(add.as_bytes(), vec![0x48, 0x83, 0xC0, 0x01]);
+
+        let mut mov = Mov(Rax, 1i64.into());
+        mov.minimize_immediates();
+        assert_eq!(
+            mov.as_bytes(),
+            vec![0x48, 0xC7, 0xC0, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn add_extended_register() {
+        assert_eq!(Add(R8, Rax.into()).as_bytes(), vec![0x4C, 0x03, 0xC0]);
+    }
+
+    mod condition {
+        use super::*;
+        use crate::asm::{Condition, Memory};
+
+        #[test]
+        fn jcc() {
+            let mut target = Memory::from("foo");
+            target.set_short(true);
+            target.set_addr(-2);
+            assert_eq!(Jcc(Condition::A, target).as_bytes(), vec![0x77, 0xFE]);
+            assert_eq!(
+                Jcc(Condition::Be, "foo".into()).as_bytes(),
+                vec![0x0F, 0x86, 0, 0, 0, 0]
+            );
+            assert_eq!(Jcc(Condition::Be, "foo".into()).as_asm(), "jbe foo");
+        }
+
+        #[test]
+        fn short_displacement() {
+            // -100 + 50 fits in a rel8, -100 + 300 doesn't
+            let mut target = Memory::from("foo").disp(50);
+            target.set_short(true);
+            target.set_addr(-100);
+            assert_eq!(Je(target.clone()).encode(), Ok(vec![0x74, 0xCE]));
+
+            let mut target = target.disp(300);
+            target.set_addr(-100);
+            assert_eq!(
+                Je(target).encode(),
+                Err(AsmError::DisplacementOverflow {
+                    label: "foo".into(),
+                    value: 200
+                })
+            );
+        }
+
+        #[test]
+        fn set() {
+            assert_eq!(Set(Condition::E, Al).as_bytes(), vec![0x0F, 0x94, 0xC0]);
+            assert_eq!(
+                Set(Condition::E, Sil).as_bytes(),
+                vec![0x40, 0x0F, 0x94, 0xC6]
+            );
+            assert_eq!(
+                Set(Condition::E, R8b).as_bytes(),
+                vec![0x41, 0x0F, 0x94, 0xC0]
+            );
+            assert_eq!(Set(Condition::Ae, R8b).as_asm(), "setae r8b");
+        }
+
+        #[test]
+        fn cmov() {
+            assert_eq!(
+                Cmov(Condition::E, Rax, R9.into()).as_bytes(),
+                vec![0x49, 0x0F, 0x44, 0xC1]
+            );
+            assert_eq!(
+                Cmov(Condition::G, R8, Memory::base(Rax).into()).as_bytes(),
+                vec![0x4C, 0x0F, 0x4F, 0x00]
+            );
+            assert_eq!(
+                Cmov(Condition::G, R8, Memory::base(Rax).into()).as_asm(),
+                "cmovg r8, [rax]"
+            );
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(Cqo.as_bytes(), vec![0x48, 0x99]);
+        assert_eq!(Cdq.as_bytes(), vec![0x99]);
+        assert_eq!(Cwd.as_bytes(), vec![0x66, 0x99]);
+        assert_eq!(Div(R9).as_bytes(), vec![0x49, 0xF7, 0xF1]);
+        assert_eq!(Mul(Rbx).as_bytes(), vec![0x48, 0xF7, 0xE3]);
+        assert_eq!(
+            Adc(Rax, 5i8.into()).as_bytes(),
+            vec![0x48, 0x83, 0xD0, 0x05]
+        );
+        assert_eq!(
+            Sbb(Rax, 5i8.into()).as_bytes(),
+            vec![0x48, 0x83, 0xD8, 0x05]
+        );
+        assert_eq!(Sbb(R8, Rax.into()).as_bytes(), vec![0x4C, 0x1B, 0xC0]);
+        assert_eq!(Adc(Rax, R8.into()).as_asm(), "adc rax, r8");
+    }
+
+    mod lea {
+        use super::*;
+        use crate::asm::{Memory, Scale};
+
+        #[test]
+        fn base_index_disp() {
+            let mem = Memory::base(Rbx).index(Rcx, Scale::Four).disp(8);
+            assert_eq!(Lea(Rax, mem).as_bytes(), vec![0x48, 0x8D, 0x44, 0x8B, 0x08]);
+        }
+
+        #[test]
+        fn extended_registers() {
+            let mem = Memory::base(R12).index(R13, Scale::Eight);
+            assert_eq!(Lea(R9, mem).as_bytes(), vec![0x4F, 0x8D, 0x0C, 0xEC]);
+        }
+
+        #[test]
+        fn special_bases() {
+            assert_eq!(
+                Lea(Rax, Memory::base(Rbp)).as_bytes(),
+                vec![0x48, 0x8D, 0x45, 0x00]
+            );
+            assert_eq!(
+                Lea(Rax, Memory::base(Rsp).disp(16)).as_bytes(),
+                vec![0x48, 0x8D, 0x44, 0x24, 0x10]
+            );
+            assert_eq!(
+                Lea(Rax, Memory::base(Rbx).disp(0x1000)).as_bytes(),
+                vec![0x48, 0x8D, 0x83, 0x00, 0x10, 0x00, 0x00]
+            );
+        }
+
+        #[test]
+        fn rip_relative() {
+            let mut mem = Memory::from("msg");
+            mem.set_addr(0x10);
+            assert_eq!(
+                Lea(Rdi, mem).as_bytes(),
+                vec![0x48, 0x8D, 0x3D, 0x10, 0x00, 0x00, 0x00]
+            );
+        }
+
+        #[test]
+        fn asm() {
+            let mem = Memory::base(R13).index(Rax, Scale::Two).disp(-4);
+            assert_eq!(Lea(Rax, mem).as_asm(), "lea rax, [r13 + rax*2 - 4]");
+            assert_eq!(Lea(Rax, Memory::from("msg")).as_asm(), "lea rax, [rel msg]");
+        }
+    }
+
+    mod memory_operands {
+        use super::*;
+        use crate::asm::{Memory, Scale};
+
+        fn rip(size: Size) -> Memory {
+            let mut mem = Memory::from("x").with_size(size);
+            mem.set_addr(0x10);
+            mem
+        }
+
+        #[test]
+        fn loads() {
+            assert_eq!(
+                Mov(Rax, Memory::base(Rbx).into()).as_bytes(),
+                vec![0x48, 0x8B, 0x03]
+            );
+            assert_eq!(
+                Mov(Al, Memory::base(Rsi).disp(1).into()).as_bytes(),
+                vec![0x8A, 0x46, 0x01]
+            );
+            assert_eq!(
+                Add(Rcx, Memory::base(R12).index(Rax, Scale::Eight).into()).as_bytes(),
+                vec![0x49, 0x03, 0x0C, 0xC4]
+            );
+            assert_eq!(
+                Cmp(Eax, rip(Size::Dword).into()).as_bytes(),
+                vec![0x3B, 0x05, 0x10, 0x00, 0x00, 0x00]
+            );
+            assert_eq!(
+                IMul(Rdx, Memory::base(Rbp).disp(-8).into()).as_bytes(),
+                vec![0x48, 0x0F, 0xAF, 0x55, 0xF8]
+            );
+            assert_eq!(
+                Xor(R8d, Memory::base(R9).into()).as_bytes(),
+                vec![0x45, 0x33, 0x01]
+            );
+            assert_eq!(
+                Push(rip(Size::Qword).into()).as_bytes(),
+                vec![0xFF, 0x35, 0x10, 0x00, 0x00, 0x00]
+            );
+        }
+
+        #[test]
+        fn addresses() {
+            let mut mem = Memory::from("x");
+            mem.set_addr(0x401000);
+
+            assert_eq!(
+                Push(mem.clone().into()).as_bytes(),
+                vec![0x68, 0x00, 0x10, 0x40, 0x00]
+            );
+            assert_eq!(
+                Add(Rax, mem.clone().into()).as_bytes(),
+                vec![0x48, 0x81, 0xC0, 0x00, 0x10, 0x40, 0x00]
+            );
+            assert_eq!(Add(Rax, mem.into()).as_asm(), "add rax, x");
+        }
+
+        #[test]
+        fn stores() {
+            assert_eq!(
+                Store(Memory::base(Rdi), Al.into()).as_bytes(),
+                vec![0x88, 0x07]
+            );
+            assert_eq!(
+                Store(Memory::base(Rsp).disp(8), R9.into()).as_bytes(),
+                vec![0x4C, 0x89, 0x4C, 0x24, 0x08]
+            );
+            assert_eq!(
+                Store(Memory::base(Rdi).with_size(Size::Byte), 0x41.into()).as_bytes(),
+                vec![0xC6, 0x07, 0x41]
+            );
+            assert_eq!(
+                Store(rip(Size::Dword), 5.into()).as_bytes(),
+                vec![0xC7, 0x05, 0x10, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00]
+            );
+            assert_eq!(
+                Store(Memory::base(Rax).with_size(Size::Word), 0x1234.into()).as_bytes(),
+                vec![0x66, 0xC7, 0x00, 0x34, 0x12]
+            );
+            assert_eq!(
+                Store(Memory::base(Rax).with_size(Size::Qword), (-1).into()).as_bytes(),
+                vec![0x48, 0xC7, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]
+            );
+        }
+
+        #[test]
+        fn asm() {
+            assert_eq!(
+                Mov(Rax, rip(Size::Qword).into()).as_asm(),
+                "mov rax, qword [rel x]"
+            );
+            assert_eq!(
+                Store(Memory::base(Rdi).with_size(Size::Byte), 0.into()).as_asm(),
+                "mov byte [rdi], 0"
+            );
+        }
     }
 }
@@ -7,6 +7,8 @@ pub enum Operand {
     /// An immediate value
     Imm(Immediate),
     Reg(Register),
+    /// An expression resolved during backpatch, encoded as a 32 bits immediate.
+    Expr(Expression),
 }
 
 impl AsBytes for Operand {
@@ -15,6 +17,7 @@ impl AsBytes for Operand {
             Operand::Mem(a) => a.as_bytes(),
             Operand::Imm(i) => i.as_bytes(),
             Operand::Reg(r) => r.as_bytes(),
+            Operand::Expr(e) => e.value().as_bytes(),
         }
     }
 }
@@ -34,6 +37,12 @@ where
     }
 }
 
+impl From<Expression> for Operand {
+    fn from(value: Expression) -> Self {
+        Self::Expr(value)
+    }
+}
+
 impl From<Register> for Operand {
     fn from(value: Register) -> Self {
         Self::Reg(value)
@@ -46,6 +55,7 @@ impl AsAsm for Operand {
             Operand::Mem(a) => a.as_asm(),
             Operand::Imm(i) => i.as_asm(),
             Operand::Reg(r) => r.as_asm(),
+            Operand::Expr(e) => e.as_asm(),
         }
     }
 }
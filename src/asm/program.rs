@@ -1,42 +1,237 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+mod control_flow;
 mod data_section;
+mod function;
+mod runtime;
+mod startup;
 
-pub use data_section::DataSection;
+pub use control_flow::LoopLabels;
+pub use data_section::{DataSection, DataValue};
+pub use function::{Frame, Function};
+pub use runtime::Routine;
+pub use startup::{ARGC, ARGV, ENVP};
 
-use super::{AsAsm, Mnemonic, Operand};
-use crate::{patchable::Patchable, prelude::AsBytes};
+use super::{
+    syscalls::LinuxSyscall, AsAsm, AsmError, Diagnostic, Expression, LabelId, Location, Mnemonic,
+    Operand, Span,
+};
+use crate::{
+    patchable::{Patchable, Segment},
+    prelude::AsBytes,
+};
 
-#[derive(Default, Clone)]
+/// A named constant, see [`Program::constant`].
+type Constant = (String, Expression);
+
+#[derive(Clone)]
 pub struct Program {
     pub instructions: Vec<Mnemonic>,
+    rodata: DataSection,
     data: DataSection,
+    constants: Vec<Constant>,
+    /// Source span of instructions, by index, see [`Program::span`].
+    spans: HashMap<usize, Span>,
+    /// Number of labels created by [`Program::new_label`].
+    label_count: usize,
+    minimal_immediates: bool,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self {
+            instructions: Vec::new(),
+            rodata: DataSection::read_only(),
+            data: DataSection::default(),
+            constants: Vec::new(),
+            spans: HashMap::new(),
+            label_count: 0,
+            minimal_immediates: false,
+        }
+    }
 }
 
 impl Program {
     /// Adds an instruction to the program.
     #[allow(clippy::should_implement_trait)]
-    pub fn add(mut self, mnemonic: Mnemonic) -> Self {
+    pub fn add(mut self, mut mnemonic: Mnemonic) -> Self {
+        if self.minimal_immediates {
+            mnemonic.minimize_immediates();
+        }
+
         self.instructions.push(mnemonic);
         self
     }
 
-    pub fn insert_data(mut self, key: &str, value: &str) -> Self {
+    /// Inserts instructions before the one at `index`, moving the spans of the following ones.
+    fn insert(mut self, index: usize, mnemonics: Vec<Mnemonic>) -> Self {
+        let len = mnemonics.len();
+        self.spans = self
+            .spans
+            .into_iter()
+            .map(|(i, span)| (if i >= index { i + len } else { i }, span))
+            .collect();
+
+        let minimal = self.minimal_immediates;
+        self.instructions.splice(
+            index..index,
+            mnemonics.into_iter().map(|mut mnemonic| {
+                if minimal {
+                    mnemonic.minimize_immediates();
+                }
+                mnemonic
+            }),
+        );
+        self
+    }
+
+    /// Attaches a source span to the last added instruction, e.g. the position of the statement
+    /// it was generated from, reported along with its errors.
+    pub fn span(mut self, span: Span) -> Self {
+        if let Some(index) = self.instructions.len().checked_sub(1) {
+            self.spans.insert(index, span);
+        }
+        self
+    }
+
+    /// Encodes every immediate of the program, already added or not, with the shortest encoding
+    /// possible, regardless of the type it was created with.
+    ///
+    /// For example `Add(Rax, 1.into())` is then encoded with an 8 bits immediate.
+    pub fn minimal_immediates(mut self) -> Self {
+        self.minimal_immediates = true;
+
+        for inst in &mut self.instructions {
+            inst.minimize_immediates();
+        }
+
+        self
+    }
+
+    /// Inserts a writable data entry, e.g. a string, a scalar, an array or a
+    /// [`DataValue::Fill`].
+    pub fn insert_data(mut self, key: &str, value: impl Into<DataValue>) -> Self {
         self.data.insert(key.into(), value.into());
         self
     }
 
+    /// Inserts a read-only data entry, such as a string literal.
+    pub fn insert_rodata(mut self, key: &str, value: impl Into<DataValue>) -> Self {
+        self.rodata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Defines a named constant, e.g. `MSG_LEN equ msg_end - msg`, usable in any
+    /// [`Expression`].
+    ///
+    /// Its value is computed during backpatch, and can refer to any label or constant, even one
+    /// defined later. Constants are resolved in dependency order, so a constant can't depend on
+    /// itself, see [`Program::check_labels`].
+    pub fn constant(mut self, name: &str, value: impl Into<Expression>) -> Self {
+        self.constants.push((name.into(), value.into()));
+        self
+    }
+
+    /// Aligns the next writable data entry on a multiple of `align`, padding with zeros.
+    pub fn align_data(mut self, align: u32) -> Self {
+        self.data.align(align);
+        self
+    }
+
+    /// Aligns the next read-only data entry on a multiple of `align`, padding with zeros.
+    pub fn align_rodata(mut self, align: u32) -> Self {
+        self.rodata.align(align);
+        self
+    }
+
+    /// Inserts a jump table in the data, holding the absolute address of each label.
+    ///
+    /// Meant to be used with an indirect jump, e.g. `jmp [table + rax*8]`.
+    pub fn jump_table(mut self, key: &str, labels: &[&str]) -> Self {
+        let entries = labels.iter().map(|l| (l.to_string(), 0)).collect();
+        self.rodata
+            .insert(key.into(), DataValue::Addresses(entries));
+        self
+    }
+
+    /// Inserts a jump table in the data, holding the 32 bits offset of each label from the start
+    /// of the table.
+    ///
+    /// The table address must be added to the loaded offset before jumping, which keeps the table
+    /// position independent.
+    pub fn relative_jump_table(mut self, key: &str, labels: &[&str]) -> Self {
+        let entries = labels.iter().map(|l| (l.to_string(), 0)).collect();
+        self.rodata.insert(key.into(), DataValue::Offsets(entries));
+        self
+    }
+
     pub fn data(&self) -> &DataSection {
         &self.data
     }
 
+    pub fn rodata(&self) -> &DataSection {
+        &self.rodata
+    }
+
+    /// The data sections holding entries, in the order they are mapped.
+    fn sections(&self) -> impl Iterator<Item = &DataSection> {
+        [&self.rodata, &self.data]
+            .into_iter()
+            .filter(|section| !section.as_bytes().is_empty())
+    }
+
     /// Creates a label in the program.
     ///
     /// Useful to jump to or to display in the output assembly.
+    ///
+    /// A label starting with a dot, e.g. `.loop`, is local to the preceding non-local label, such
+    /// as the [function](Program::func) it appears in. It is referenced as `.loop` within that
+    /// scope, or by its full name, e.g. `print.loop`, from anywhere.
     pub fn label(self, label: &str) -> Self {
         self.add(Mnemonic::Label(label.into()))
     }
 
+    /// Creates a unique label named after `hint`, e.g. `..@if_end_0`, to be placed later with
+    /// [`Program::label`].
+    ///
+    /// As in nasm, a label starting with `..@` never clashes with the user ones and is not a
+    /// scope for local labels. Useful for code generators, e.g. to jump forward to the end of an
+    /// `IF`:
+    ///
+    /// ```
+    /// # use tiny_elf::asm::{Mnemonic::*, Program, Register::*};
+    /// let mut program = Program::default();
+    /// let end = program.new_label("if_end");
+    ///
+    /// let program = program
+    ///     .add(Cmp(Rax, 0.into()))
+    ///     .add(Je((&end).into()))
+    ///     .add(Inc(Rax))
+    ///     .label(&end);
+    /// ```
+    pub fn new_label(&mut self, hint: &str) -> LabelId {
+        let label = LabelId(format!("..@{hint}_{}", self.label_count));
+        self.label_count += 1;
+        label
+    }
+
+    /// Aligns the next instruction on a multiple of `align`, padding with NOPs.
+    ///
+    /// Useful to align loop heads. The program start address is aligned accordingly by the
+    /// [`Elf`](crate::Elf).
+    ///
+    /// `align` must be a power of two, which is checked when encoding.
+    pub fn align(self, align: u32) -> Self {
+        self.add(Mnemonic::Align(align, 0))
+    }
+
+    /// Performs a Linux system call, see [`syscalls`](super::syscalls).
+    pub fn syscall(self, call: LinuxSyscall) -> Self {
+        call.instructions()
+            .into_iter()
+            .fold(self, |program, inst| program.add(inst))
+    }
+
     /// Declare a new function.
     ///
     /// This is a convenience method to write the prolog of a function.
@@ -56,54 +251,437 @@ impl Program {
 
         self.add(Mov(Rsp, Rbp.into())).add(Pop(Rbp)).add(Ret)
     }
-}
 
-impl Patchable for Program {
-    fn backpatch(&mut self, _start_addr: u32, data_addr: u32) {
+    /// Computes the padding of each [`Mnemonic::Align`] from the current instruction sizes.
+    fn update_alignments(&mut self) {
+        let mut current_byte: u32 = 0;
+
+        for inst in self.instructions.iter_mut() {
+            if let Mnemonic::Align(align, padding) = inst {
+                // an invalid alignment is reported when encoding
+                let align = if align.is_power_of_two() { *align } else { 1 };
+                *padding = current_byte.next_multiple_of(align) - current_byte;
+            }
+
+            current_byte += inst.as_bytes().len() as u32;
+        }
+    }
+
+    /// Checks that every label, data entry and constant is defined once, that no constant depends
+    /// on itself, and that every referenced label is defined.
+    ///
+    /// Done by backpatch, but useful to report errors before any layout.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::Labels`] holding every duplicate definition and every undefined reference,
+    /// in program order.
+    pub fn check_labels(&self) -> Result<(), AsmError> {
+        let mut duplicates = Vec::new();
+        let mut undefined = Vec::new();
+
+        let scopes = self.scopes();
+
+        let mut code_labels = HashSet::new();
+        for (index, inst) in self.instructions.iter().enumerate() {
+            if let Mnemonic::Label(label) = inst {
+                let label = qualify(&scopes[index], label);
+                if !code_labels.insert(label.clone()) {
+                    duplicates.push((label, self.location(index)));
+                }
+            }
+        }
+
+        let mut symbols = code_labels.clone();
+        for key in self.rodata.keys().chain(self.data.keys()) {
+            if !symbols.insert(key.into()) {
+                duplicates.push((key.into(), Location::Data(key.into())));
+            }
+        }
+
+        for (index, inst) in self.instructions.iter().enumerate() {
+            let mut inst = inst.clone();
+            let scope = &scopes[index];
+
+            if let Some(target) = inst.branch_target_mut() {
+                let label = qualify(scope, target.label());
+                if !label.is_empty() && !code_labels.contains(&label) {
+                    undefined.push((label, self.location(index)));
+                }
+            }
+            if let Some(mem) = inst.memory_operand_mut() {
+                let label = qualify(scope, mem.label());
+                if !label.is_empty() && !self.is_symbol(&symbols, &label) {
+                    undefined.push((label, self.location(index)));
+                }
+            }
+            if let Some(Operand::Expr(expr)) = inst.immediate_operand_mut() {
+                for symbol in expr.symbols() {
+                    let symbol = qualify(scope, symbol);
+                    if !self.is_symbol(&symbols, &symbol) {
+                        undefined.push((symbol, self.location(index)));
+                    }
+                }
+            }
+        }
+
+        for (key, label) in self.rodata.references().chain(self.data.references()) {
+            if !self.is_symbol(&symbols, label) {
+                undefined.push((label.into(), Location::Data(key.into())));
+            }
+        }
+
+        for (name, value) in &self.constants {
+            for symbol in value.symbols() {
+                if !self.is_symbol(&symbols, symbol) {
+                    undefined.push((symbol.into(), Location::Constant(name.clone())));
+                }
+            }
+
+            if !symbols.insert(name.clone()) {
+                duplicates.push((name.clone(), Location::Constant(name.clone())));
+            }
+        }
+
+        let (_, unresolved) = self.sorted_constants();
+        let circular = unresolved
+            .iter()
+            .filter(|(name, _)| depends_on(name, name, &unresolved))
+            .map(|(name, _)| Diagnostic {
+                error: AsmError::CircularConstant(name.clone()),
+                location: Location::Constant(name.clone()),
+            });
+
+        let diagnostics: Vec<_> = duplicates
+            .into_iter()
+            .map(|(label, location)| Diagnostic {
+                error: AsmError::DuplicateLabel(label),
+                location,
+            })
+            .chain(undefined.into_iter().map(|(label, location)| Diagnostic {
+                error: AsmError::UndefinedLabel(label),
+                location,
+            }))
+            .chain(circular)
+            .collect();
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(AsmError::Labels(diagnostics))
+        }
+    }
+
+    /// Checks that every instruction can be encoded, e.g. that its operands have the same size.
+    ///
+    /// Done by backpatch before any layout, then once immediates and addresses are resolved.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::Encoding`] holding the error of every instruction that can't be encoded, in
+    /// program order.
+    pub fn check_encoding(&self) -> Result<(), AsmError> {
+        let diagnostics: Vec<_> = self
+            .instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, inst)| {
+                inst.encode().err().map(|error| Diagnostic {
+                    error,
+                    location: self.location(index),
+                })
+            })
+            .collect();
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(AsmError::Encoding(diagnostics))
+        }
+    }
+
+    /// Encodes the program, usually once backpatched.
+    ///
+    /// Prefer it to [`AsBytes::as_bytes`], which gives no bytes for an instruction that can't be
+    /// encoded.
+    ///
+    /// # Errors
+    ///
+    /// [`AsmError::Encoding`], see [`Program::check_encoding`].
+    pub fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        self.check_encoding()?;
+        Ok(self.as_bytes())
+    }
+
+    /// The constants in dependency order, each one after the constants its value refers to,
+    /// followed by the ones which can't be ordered as they depend on a cycle.
+    fn sorted_constants(&self) -> (Vec<&Constant>, Vec<&Constant>) {
+        let is_constant = |symbol: &str| self.constants.iter().any(|(name, _)| name == symbol);
+
+        let mut sorted = Vec::new();
+        let mut resolved = HashSet::new();
+        let mut pending: Vec<_> = self.constants.iter().collect();
+        loop {
+            let (ready, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(_, value)| {
+                value
+                    .symbols()
+                    .into_iter()
+                    .all(|symbol| resolved.contains(symbol) || !is_constant(symbol))
+            });
+            if ready.is_empty() {
+                return (sorted, rest);
+            }
+
+            resolved.extend(ready.iter().map(|(name, _)| name.as_str()));
+            sorted.extend(ready);
+            pending = rest;
+        }
+    }
+
+    /// Determines if `label` is a label, a data entry or any constant.
+    fn is_symbol(&self, symbols: &HashSet<String>, label: &str) -> bool {
+        symbols.contains(label) || self.constants.iter().any(|(name, _)| name == label)
+    }
+
+    /// The non-local label preceding each instruction, which scopes its local labels.
+    ///
+    /// See [`Program::label`].
+    fn scopes(&self) -> Vec<String> {
+        let mut scope = String::new();
+
+        self.instructions
+            .iter()
+            .map(|inst| {
+                if let Mnemonic::Label(label) = inst {
+                    // neither local nor generated labels
+                    if !label.starts_with('.') {
+                        scope.clone_from(label);
+                    }
+                }
+                scope.clone()
+            })
+            .collect()
+    }
+
+    /// The location of the instruction at `index`, along with its span.
+    fn location(&self, index: usize) -> Location {
+        Location::Instruction {
+            index,
+            span: self.spans.get(&index).cloned(),
+        }
+    }
+
+    /// Computes the offset of each label from the start of the program, given its
+    /// [scopes](Program::scopes).
+    fn label_offsets(&self, scopes: &[String]) -> HashMap<String, i32> {
         let mut labels = HashMap::<String, i32>::default();
-        let data_labels = self.data.addresses(data_addr);
         let mut current_byte: i32 = 0;
 
-        for inst in self.instructions.iter() {
+        for (inst, scope) in self.instructions.iter().zip(scopes) {
             current_byte += inst.as_bytes().len() as i32;
 
             if let Mnemonic::Label(label) = inst {
-                labels.insert(label.clone(), current_byte);
+                labels.insert(qualify(scope, label), current_byte);
             }
         }
 
-        let mut current_byte: i32 = 0;
+        labels
+    }
+}
+
+/// Determines if `label` is local to the preceding non-local label, see [`Program::label`].
+fn is_local(label: &str) -> bool {
+    label.starts_with('.') && !label.starts_with("..@")
+}
+
+/// Determines if the value of the constant `from` refers to `to`, directly or through other
+/// `constants`.
+fn depends_on(from: &str, to: &str, constants: &[&Constant]) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(current) = stack.pop() {
+        for (_, value) in constants.iter().filter(|(name, _)| name == current) {
+            for symbol in value.symbols() {
+                if symbol == to {
+                    return true;
+                }
+                if visited.insert(symbol) {
+                    stack.push(symbol);
+                }
+            }
+        }
+    }
+
+    false
+}
 
+/// The full name of `label` referenced within `scope`, e.g. `print.loop` for `.loop`.
+fn qualify(scope: &str, label: &str) -> String {
+    if is_local(label) {
+        format!("{scope}{label}")
+    } else {
+        label.into()
+    }
+}
+
+impl Patchable for Program {
+    type Error = AsmError;
+
+    /// Branch relaxation: chooses the short (rel8) encoding of every jump whose target is close
+    /// enough.
+    ///
+    /// All jumps start short and every one whose displacement does not fit in 8 bits is made
+    /// near. As this grows the program, label offsets are recomputed and the pass repeated until
+    /// no jump changes. Jumps are never made short again, so this ends.
+    fn relax(&mut self) {
         for inst in self.instructions.iter_mut() {
+            if let Some(target) = inst.relaxable_target_mut() {
+                target.set_short(true);
+            }
+        }
+
+        // jumps never change labels
+        let scopes = self.scopes();
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            self.update_alignments();
+            let labels = self.label_offsets(&scopes);
+            let mut current_byte: i32 = 0;
+
+            for (inst, scope) in self.instructions.iter_mut().zip(&scopes) {
+                current_byte += inst.as_bytes().len() as i32;
+
+                let Some(target) = inst.relaxable_target_mut() else {
+                    continue;
+                };
+
+                let disp = target.displacement() as i64;
+                let fits = labels
+                    .get(&qualify(scope, target.label()))
+                    .is_some_and(|label_addr| {
+                        i8::try_from(*label_addr as i64 - current_byte as i64 + disp).is_ok()
+                    });
+
+                if target.is_short() && !fits {
+                    target.set_short(false);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Resolves every label, constant and expression of the program and its data.
+    ///
+    /// # Errors
+    ///
+    /// - [`AsmError::Labels`] if a label, data entry or constant is defined twice, or if a
+    ///   referenced label is never defined, see [`Program::check_labels`].
+    /// - [`AsmError::Encoding`] if an instruction can't be encoded, see
+    ///   [`Program::check_encoding`].
+    /// - [`AsmError::DisplacementOverflow`] if a label is too far to be referenced.
+    fn backpatch(&mut self, start_addr: u32, segment_addrs: &[u32]) -> Result<(), AsmError> {
+        self.check_labels()?;
+        // label offsets can't be computed from instructions without encoding
+        self.check_encoding()?;
+        self.update_alignments();
+        let scopes = self.scopes();
+        let labels = self.label_offsets(&scopes);
+
+        let mut segment_addrs = segment_addrs.iter().copied();
+        let mut section_addrs = [0; 2];
+        for (section, addr) in [&self.rodata, &self.data]
+            .into_iter()
+            .zip(section_addrs.iter_mut())
+        {
+            if !section.as_bytes().is_empty() {
+                *addr = segment_addrs.next().unwrap_or_default();
+            }
+        }
+        let [rodata_addr, data_addr] = section_addrs;
+
+        let mut data_labels = self.rodata.addresses(rodata_addr);
+        data_labels.extend(self.data.addresses(data_addr));
+
+        let mut symbols: HashMap<String, i64> = labels
+            .iter()
+            .map(|(label, offset)| (label.clone(), start_addr as i64 + *offset as i64))
+            .chain(
+                data_labels
+                    .iter()
+                    .map(|(label, addr)| (label.clone(), *addr as i64)),
+            )
+            .collect();
+        for (name, value) in self.sorted_constants().0 {
+            symbols.insert(name.clone(), value.evaluate(&symbols)?);
+        }
+        let mut current_byte: i32 = 0;
+
+        for (inst, scope) in self.instructions.iter_mut().zip(&scopes) {
             current_byte += inst.as_bytes().len() as i32;
 
-            match inst {
-                Mnemonic::Call(addr)
-                | Mnemonic::Je(addr)
-                | Mnemonic::Jne(addr)
-                | Mnemonic::Jg(addr)
-                | Mnemonic::Jge(addr)
-                | Mnemonic::Jl(addr)
-                | Mnemonic::Jle(addr)
-                | Mnemonic::Jmp(addr)
-                    if !addr.label().is_empty() =>
-                {
-                    let label_addr = labels
-                        .get(addr.label())
-                        .unwrap_or_else(|| panic!("Label '{}' not found", addr.label()));
-
-                    addr.set_addr(label_addr - current_byte);
+            if let Some(Operand::Expr(expr)) = inst.immediate_operand_mut() {
+                expr.resolve_with(|symbol| symbols.get(&qualify(scope, symbol)).copied())?;
+            }
+
+            let address_operand = inst.has_address_operand();
+
+            if let Some(target) = inst.branch_target_mut() {
+                if !target.label().is_empty() {
+                    let label = qualify(scope, target.label());
+                    let label_addr = labels.get(&label).ok_or(AsmError::UndefinedLabel(label))?;
+
+                    target.set_addr(label_addr - current_byte);
                 }
-                Mnemonic::Mov(_, Operand::Mem(addr)) if !addr.label().is_empty() => {
-                    let data_addr = *data_labels
-                        .get(addr.label())
-                        .unwrap_or_else(|| panic!("Label '{}' not found", addr.label()));
+            }
+
+            if let Some(mem) = inst.memory_operand_mut() {
+                if !mem.label().is_empty() {
+                    let label = qualify(scope, mem.label());
+                    let label_addr = *symbols.get(&label).ok_or(AsmError::UndefinedLabel(label))?;
 
-                    addr.set_addr(data_addr as i32);
+                    let value = if mem.is_rip_relative() && !address_operand {
+                        label_addr - (start_addr as i64 + current_byte as i64)
+                    } else {
+                        label_addr
+                    };
+                    mem.set_addr(i32::try_from(value).map_err(|_| {
+                        AsmError::DisplacementOverflow {
+                            label: mem.label().into(),
+                            value,
+                        }
+                    })?);
                 }
-                _ => (),
             }
         }
+
+        self.rodata.backpatch(&symbols, rodata_addr)?;
+        self.data.backpatch(&symbols, data_addr)?;
+
+        // resolved immediates may not fit
+        self.check_encoding()
+    }
+
+    fn alignment(&self) -> u32 {
+        self.instructions
+            .iter()
+            .filter_map(|inst| match inst {
+                Mnemonic::Align(align, _) if align.is_power_of_two() => Some(*align),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(1)
+    }
+
+    fn segments(&self) -> Vec<Segment> {
+        self.sections()
+            .map(|section| Segment {
+                data: section.as_bytes(),
+                flags: section.flags(),
+                align: section.alignment(),
+            })
+            .collect()
     }
 }
 
@@ -111,7 +689,16 @@ impl AsAsm for Program {
     fn as_asm(&self) -> String {
         let mut out: String = "GLOBAL _start\n\n".into();
 
-        out += &(self.data.as_asm() + "\n\n");
+        if !self.constants.is_empty() {
+            for (name, value) in &self.constants {
+                out += &format!("{name} equ {}\n", value.as_asm());
+            }
+            out += "\n";
+        }
+
+        for section in self.sections() {
+            out += &(section.as_asm() + "\n\n");
+        }
 
         out += "section .text\n";
         out += "_start:\n";
@@ -136,3 +723,386 @@ impl AsBytes for Program {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Memory, Mnemonic::*, Register::*, Scale, Size};
+    use crate::program_header::Flags;
+
+    mod relax {
+        use super::*;
+
+        #[test]
+        fn short_loop() {
+            let mut program = Program::default()
+                .label("loop")
+                .add(Dec(Rcx))
+                .add(Jne("loop".into()));
+
+            program.relax();
+            program.backpatch(0, &[]).unwrap();
+
+            // dec rcx; jne -5
+            assert_eq!(program.as_bytes(), vec![0x48, 0xFF, 0xC9, 0x75, 0xFB]);
+        }
+
+        #[test]
+        fn far_target() {
+            let mut program = Program::default().add(Jmp("end".into()));
+            for _ in 0..20 {
+                program = program.add(Mov(Rax, 1.into()));
+            }
+            program = program.label("end");
+
+            program.relax();
+            program.backpatch(0, &[]).unwrap();
+
+            // 20 moves of 7 bytes don't fit in a rel8
+            assert_eq!(program.as_bytes()[..5], [0xE9, 140, 0, 0, 0]);
+        }
+
+        #[test]
+        fn forced_near() {
+            let mut program = Program::default()
+                .add(Jmp(Memory::from("end").near()))
+                .label("end");
+
+            program.relax();
+            program.backpatch(0, &[]).unwrap();
+
+            assert_eq!(program.as_bytes(), vec![0xE9, 0, 0, 0, 0]);
+            assert_eq!(program.instructions[0].as_asm(), "jmp near end");
+        }
+
+        #[test]
+        fn displacement() {
+            let mut program = Program::default()
+                .label("l")
+                .add(Jmp(Memory::from("l").disp(200)))
+                .add(Ret);
+
+            program.relax();
+            program.backpatch(0, &[]).unwrap();
+
+            // l + 200 is 195 bytes after the jump
+            assert_eq!(program.as_bytes()[..5], [0xE9, 195, 0, 0, 0]);
+        }
+    }
+
+    mod align {
+        use super::*;
+
+        #[test]
+        fn code() {
+            let mut program = Program::default()
+                .add(Ret)
+                .align(16)
+                .label("aligned")
+                .add(Jmp("aligned".into()));
+
+            program.relax();
+            program.backpatch(0, &[]).unwrap();
+
+            let bytes = program.as_bytes();
+            assert_eq!(bytes.len(), 18);
+            // 15 bytes of padding: a 9 bytes and a 6 bytes NOP
+            assert_eq!(bytes[1..4], [0x66, 0x0F, 0x1F]);
+            assert_eq!(bytes[10..13], [0x66, 0x0F, 0x1F]);
+            assert_eq!(bytes[16..], [0xEB, 0xFE]);
+            assert_eq!(program.alignment(), 16);
+        }
+
+        #[test]
+        fn data() {
+            let program = Program::default()
+                .insert_data("string", "abc")
+                .align_data(8)
+                .insert_data("number", 1u64);
+
+            assert_eq!(
+                program.data().as_bytes(),
+                b"abc\0\0\0\0\0\x01\0\0\0\0\0\0\0"
+            );
+            assert_eq!(program.data().addresses(0x100)["number"], 0x108);
+            assert_eq!(program.segments()[0].align, 8);
+            assert_eq!(
+                program.data().as_asm(),
+                "section .data\n    string db \"abc\"\n    align 8, db 0\n    number dq 1\n"
+            );
+        }
+    }
+
+    #[test]
+    fn local_labels() {
+        let mut program = Program::default()
+            .label("first")
+            .label(".loop")
+            .add(Dec(Rcx))
+            .add(Jne(".loop".into()))
+            .label("second")
+            .label(".loop")
+            .add(Jmp(".loop".into()))
+            .add(Jmp("first.loop".into()));
+
+        assert_eq!(program.check_labels(), Ok(()));
+        program.relax();
+        program.backpatch(0, &[]).unwrap();
+
+        assert_eq!(
+            program.as_bytes(),
+            vec![
+                0x48, 0xFF, 0xC9, // dec rcx
+                0x75, 0xFB, // jne first.loop
+                0xEB, 0xFE, // jmp second.loop
+                0xEB, 0xF7, // jmp first.loop
+            ]
+        );
+        assert!(program.as_asm().contains("\n.loop:\n    jmp .loop\n"));
+
+        let program = Program::default()
+            .label(".loop")
+            .add(Jmp("other.loop".into()));
+        assert_eq!(
+            program.check_labels().unwrap_err().to_string(),
+            "instruction 1: Label 'other.loop' not found"
+        );
+    }
+
+    #[test]
+    fn new_labels() {
+        let mut program = Program::default();
+        let first = program.new_label("if_end");
+        let second = program.new_label("if_end");
+        assert_eq!(&*first, "..@if_end_0");
+        assert_eq!(&*second, "..@if_end_1");
+
+        let mut program = program
+            .label("func")
+            .add(Jmp((&second).into()))
+            .label(&first)
+            .label(".local")
+            .label(&second)
+            .add(Jmp(".local".into()));
+
+        program.relax();
+        program.backpatch(0, &[]).unwrap();
+
+        // generated labels don't start a new scope for `.local`
+        assert_eq!(program.as_bytes(), vec![0xEB, 0x00, 0xEB, 0xFE]);
+    }
+
+    #[test]
+    fn expressions() {
+        let mut program = Program::default()
+            .insert_data("s", "abcd")
+            .insert_data("s_end", "")
+            // refers to a constant defined later
+            .constant("NEXT", Expression::from("LEN") + 1)
+            .constant("LEN", Expression::from("s_end") - "s")
+            .add(Mov(Rcx, Expression::from("LEN").into()))
+            .add(Mov(Rsi, Memory::from("s").disp(2).into()))
+            .add(Add(Rcx, Expression::from("NEXT").into()));
+
+        program.backpatch(0x1000, &[0x2000]).unwrap();
+
+        assert_eq!(
+            program.as_bytes(),
+            vec![
+                0x48, 0xC7, 0xC1, 0x04, 0x00, 0x00, 0x00, // mov rcx, LEN
+                0x48, 0xC7, 0xC6, 0x02, 0x20, 0x00, 0x00, // mov rsi, s + 2
+                0x48, 0x81, 0xC1, 0x05, 0x00, 0x00, 0x00, // add rcx, NEXT
+            ]
+        );
+        assert_eq!(program.instructions[1].as_asm(), "mov rsi, s + 2");
+        assert!(program.as_asm().contains("LEN equ s_end - s\n"));
+    }
+
+    #[test]
+    fn memory_operands() {
+        let mut program = Program::default()
+            .insert_data("x", 0u64)
+            .add(Push(Memory::from("x").into()))
+            .add(Store(Memory::from("x").with_size(Size::Dword), 1.into()))
+            .add(Cmp(Rax, Memory::from("x").with_size(Size::Qword).into()));
+
+        program.backpatch(0x1000, &[0x2000]).unwrap();
+
+        // push x
+        assert_eq!(
+            program.instructions[0].as_bytes()[1..],
+            0x2000i32.as_bytes()
+        );
+        // mov dword [rel x], 1, relative to the end of the instruction
+        assert_eq!(
+            program.instructions[1].as_bytes()[2..6],
+            0x0FF1i32.as_bytes()
+        );
+        // cmp rax, qword [rel x]
+        assert_eq!(
+            program.instructions[2].as_bytes()[3..],
+            0x0FEAi32.as_bytes()
+        );
+    }
+
+    #[test]
+    fn unresolved_memory() {
+        let mut program = Program::default().add(Add(Rax, Memory::from("missing").into()));
+        assert_eq!(
+            program.backpatch(0, &[]),
+            Err(AsmError::Labels(vec![Diagnostic {
+                error: AsmError::UndefinedLabel("missing".into()),
+                location: Location::Instruction {
+                    index: 0,
+                    span: None
+                },
+            }]))
+        );
+    }
+
+    #[test]
+    fn label_diagnostics() {
+        let program = Program::default()
+            .insert_data("x", 0u8)
+            .insert_rodata("x", "again")
+            .jump_table("table", &["start", "nowhere"])
+            .constant("LEN", Expression::from("END") - "x")
+            .constant("A", "B")
+            .constant("B", Expression::from("A") + 1)
+            // not circular itself
+            .constant("C", "A")
+            .label("start")
+            .span(0..6)
+            .add(Jmp("end".into()))
+            .span(7..14)
+            .label("start")
+            .add(Mov(Rax, Memory::from("unknown").into()));
+
+        let Err(AsmError::Labels(diagnostics)) = program.check_labels() else {
+            panic!("labels should be reported");
+        };
+        let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "instruction 2: Label 'start' defined more than once",
+                "data 'x': Label 'x' defined more than once",
+                "instruction 1 (7..14): Label 'end' not found",
+                "instruction 3: Label 'unknown' not found",
+                "data 'table': Label 'nowhere' not found",
+                "constant 'LEN': Label 'END' not found",
+                "constant 'A': Constant 'A' depends on itself",
+                "constant 'B': Constant 'B' depends on itself",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut program = Program::default()
+            .add(Inc(Rax))
+            .add(Add(Rax, Ebx.into()))
+            .span(7..14);
+        assert!(matches!(program.encode(), Err(AsmError::Encoding(_))));
+        let Err(AsmError::Encoding(diagnostics)) = program.backpatch(0, &[]) else {
+            panic!("expected an encoding error");
+        };
+        assert!(matches!(
+            diagnostics[..],
+            [Diagnostic {
+                error: AsmError::UnsupportedOperands(_),
+                location: Location::Instruction {
+                    index: 1,
+                    span: Some(_)
+                },
+            }]
+        ));
+
+        // only known once the constant is resolved
+        let mut program = Program::default()
+            .constant("BIG", 300)
+            .add(Mov(Al, Expression::from("BIG").into()));
+        assert_eq!(
+            program.backpatch(0, &[]),
+            Err(AsmError::Encoding(vec![Diagnostic {
+                error: AsmError::ImmediateOutOfRange {
+                    value: 300,
+                    size: Size::Byte
+                },
+                location: Location::Instruction {
+                    index: 0,
+                    span: None
+                },
+            }]))
+        );
+
+        // registers and alignments are checked when encoding, not when built
+        let program = Program::default()
+            .add(Lea(Rax, Memory::base(Eax)))
+            .add(Lea(Rax, Memory::base(Rax).index(Rsp, Scale::One)))
+            .align(3);
+        let Err(AsmError::Encoding(diagnostics)) = program.check_encoding() else {
+            panic!("expected an encoding error");
+        };
+        assert_eq!(diagnostics.len(), 3);
+
+        let mut program = Program::default()
+            .align_data(3)
+            .insert_data("variable", 0u8);
+        assert!(matches!(
+            program.backpatch(0, &[]),
+            Err(AsmError::UnsupportedOperands(_))
+        ));
+    }
+
+    #[test]
+    fn sections() {
+        let mut program = Program::default()
+            .insert_rodata("literal", "ro")
+            .insert_data("variable", 0u8)
+            .add(Lea(Rsi, "literal".into()))
+            .add(Lea(Rdi, "variable".into()));
+
+        let segments = program.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].flags, Flags::Readable);
+        assert_eq!(segments[1].flags, Flags::Readable | Flags::Writeable);
+
+        program.backpatch(0x1000, &[0x2000, 0x3000]).unwrap();
+
+        // lea rsi, [rel literal]; lea rdi, [rel variable]
+        assert_eq!(
+            program.instructions[0].as_bytes()[3..],
+            0x0FF9i32.as_bytes()
+        );
+        assert_eq!(
+            program.instructions[1].as_bytes()[3..],
+            0x1FF2i32.as_bytes()
+        );
+    }
+
+    #[test]
+    fn jump_tables() {
+        let mut program = Program::default()
+            .jump_table("table", &["a", "b"])
+            .relative_jump_table("offsets", &["a", "b"])
+            .label("a")
+            .add(Ret)
+            .label("b")
+            .add(Ret);
+
+        program.backpatch(0x1000, &[0x2000]).unwrap();
+
+        let mut expected = Vec::new();
+        expected.append(&mut 0x1000u64.as_bytes());
+        expected.append(&mut 0x1001u64.as_bytes());
+        expected.append(&mut (0x1000 - 0x2010i32).as_bytes());
+        expected.append(&mut (0x1001 - 0x2010i32).as_bytes());
+        assert_eq!(program.rodata().as_bytes(), expected);
+
+        assert_eq!(
+            program.rodata().as_asm(),
+            "section .rodata\n    table dq a, b\n    offsets dd a - offsets, b - offsets\n"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::Program;
+use crate::asm::{Condition, LabelId, Mnemonic::*};
+
+/// The labels of a loop, given to its body to `break` or `continue` it.
+///
+/// See [`Program::while_loop`] and [`Program::loop_forever`].
+#[derive(Debug, Clone)]
+pub struct LoopLabels {
+    /// Placed before the condition, jumping to it continues the loop.
+    pub start: LabelId,
+    /// Placed after the loop, jumping to it breaks the loop.
+    pub end: LabelId,
+}
+
+impl Program {
+    /// Emits `then` if `cc` holds, according to the flags set by the previous instructions.
+    ///
+    /// ```
+    /// # use tiny_elf::asm::{Condition, Mnemonic::*, Program, Register::*};
+    /// // if rax == 0 { rax += 1 }
+    /// let program = Program::default()
+    ///     .add(Cmp(Rax, 0.into()))
+    ///     .if_then(Condition::E, |then| then.add(Inc(Rax)));
+    /// ```
+    pub fn if_then(mut self, cc: Condition, then: impl FnOnce(Program) -> Program) -> Self {
+        let end = self.new_label("if_end");
+
+        then(self.add(Jcc(cc.negate(), (&end).into()))).label(&end)
+    }
+
+    /// Emits `then` if `cc` holds, according to the flags set by the previous instructions, or
+    /// `else_` otherwise.
+    pub fn if_cond(
+        mut self,
+        cc: Condition,
+        then: impl FnOnce(Program) -> Program,
+        else_: impl FnOnce(Program) -> Program,
+    ) -> Self {
+        let else_label = self.new_label("if_else");
+        let end = self.new_label("if_end");
+
+        let this = then(self.add(Jcc(cc.negate(), (&else_label).into())))
+            .add(Jmp((&end).into()))
+            .label(&else_label);
+        else_(this).label(&end)
+    }
+
+    /// Emits `body` while `cc` holds, according to the flags set by `cond`, which is evaluated
+    /// before each iteration.
+    ///
+    /// ```
+    /// # use tiny_elf::asm::{Condition, Mnemonic::*, Program, Register::*};
+    /// // while rcx != 0 { rax += rcx; rcx -= 1 }
+    /// let program = Program::default().while_loop(
+    ///     Condition::Ne,
+    ///     |cond| cond.add(Cmp(Rcx, 0.into())),
+    ///     |body, _| body.add(Add(Rax, Rcx.into())).add(Dec(Rcx)),
+    /// );
+    /// ```
+    pub fn while_loop(
+        self,
+        cc: Condition,
+        cond: impl FnOnce(Program) -> Program,
+        body: impl FnOnce(Program, &LoopLabels) -> Program,
+    ) -> Self {
+        self.loop_forever(|this, labels| {
+            let this = cond(this).add(Jcc(cc.negate(), (&labels.end).into()));
+            body(this, labels)
+        })
+    }
+
+    /// Emits `body` in an endless loop, which it can only leave by jumping to
+    /// [`LoopLabels::end`].
+    pub fn loop_forever(mut self, body: impl FnOnce(Program, &LoopLabels) -> Program) -> Self {
+        let labels = LoopLabels {
+            start: self.new_label("loop_start"),
+            end: self.new_label("loop_end"),
+        };
+
+        body(self.label(&labels.start), &labels)
+            .add(Jmp((&labels.start).into()))
+            .label(&labels.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::{AsAsm, Register::*},
+        patchable::Patchable,
+        prelude::AsBytes,
+    };
+
+    #[test]
+    fn if_cond() {
+        let mut program = Program::default().add(Cmp(Rax, 0.into())).if_cond(
+            Condition::E,
+            |then| then.add(Inc(Rax)),
+            |else_| else_.add(Dec(Rax)),
+        );
+
+        program.relax();
+        program.backpatch(0, &[]).unwrap();
+
+        assert_eq!(
+            program.as_bytes()[7..],
+            [
+                0x75, 0x05, // jne ..@if_else_0
+                0x48, 0xFF, 0xC0, // inc rax
+                0xEB, 0x03, // jmp ..@if_end_1
+                0x48, 0xFF, 0xC8, // dec rax
+            ]
+        );
+        assert!(program.as_asm().contains("    jne ..@if_else_0\n"));
+    }
+
+    #[test]
+    fn while_loop() {
+        let mut program = Program::default().while_loop(
+            Condition::Ne,
+            |cond| cond.add(Cmp(Rcx, 0.into())),
+            |body, labels| {
+                body.add(Dec(Rcx))
+                    .add(Cmp(Rcx, 5.into()))
+                    .add(Je((&labels.end).into()))
+            },
+        );
+
+        program.relax();
+        program.backpatch(0, &[]).unwrap();
+
+        let bytes = program.as_bytes();
+        assert_eq!(bytes[7..9], [0x74, 0x0E], "je ..@loop_end_1");
+        assert_eq!(bytes[19..21], [0x74, 0x02], "je ..@loop_end_1");
+        assert_eq!(bytes[21..], [0xEB, 0xE9], "jmp ..@loop_start_0");
+    }
+}
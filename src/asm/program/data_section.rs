@@ -1,38 +1,132 @@
 use std::collections::HashMap;
 
-use crate::{asm::AsAsm, prelude::AsBytes};
+use crate::{
+    asm::{AsAsm, AsmError},
+    prelude::AsBytes,
+    program_header::Flags,
+};
 
 mod data_entry;
 
 pub use data_entry::*;
 
+/// A data section, writable by default (`section .data`) or read-only (`section .rodata`).
 #[derive(Default, Clone)]
 pub struct DataSection {
     data: Vec<DataEntry>,
+    read_only: bool,
+    /// Alignment of the next inserted entry, see [`DataSection::align`].
+    next_align: Option<u32>,
 }
 
 impl DataSection {
-    pub fn insert(&mut self, key: String, value: String) {
-        let entry = DataEntry::new(key, value);
+    /// Creates a read-only data section.
+    pub fn read_only() -> Self {
+        Self {
+            read_only: true,
+            ..Default::default()
+        }
+    }
+
+    /// The flags of the segment this section is mapped to.
+    pub fn flags(&self) -> Flags {
+        if self.read_only {
+            Flags::Readable
+        } else {
+            Flags::Readable | Flags::Writeable
+        }
+    }
+
+    pub fn insert(&mut self, key: String, value: DataValue) {
+        let mut entry = DataEntry::new(key, value);
+        if let Some(align) = self.next_align.take() {
+            entry = entry.with_align(align);
+        }
         self.data.push(entry);
     }
 
-    pub fn addresses(&self, start_addr: u32) -> HashMap<String, u32> {
-        let mut map = HashMap::default();
+    /// Aligns the next inserted entry on a multiple of `align`, padding with zeros.
+    ///
+    /// `align` must be a power of two, which is checked by [`DataSection::backpatch`].
+    pub fn align(&mut self, align: u32) {
+        self.next_align = Some(match self.next_align {
+            Some(next) if next.is_power_of_two() && align.is_power_of_two() => next.max(align),
+            // an invalid alignment is kept to be reported
+            Some(next) if !next.is_power_of_two() => next,
+            _ => align,
+        });
+    }
+
+    /// The alignment the start address of this section must respect.
+    pub fn alignment(&self) -> u32 {
+        self.data.iter().map(DataEntry::align).max().unwrap_or(1)
+    }
+
+    /// The key of each entry, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().map(DataEntry::key)
+    }
+
+    /// The labels referenced by the entries, along with the key of the referencing entry.
+    pub fn references(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.data
+            .iter()
+            .flat_map(|entry| entry.labels().into_iter().map(|label| (entry.key(), label)))
+    }
+
+    /// Computes the offset of each entry from the start of the section.
+    fn offsets(&self) -> Vec<u32> {
+        let mut offsets = Vec::with_capacity(self.data.len());
 
         let mut len: u32 = 0;
         for entry in &self.data {
-            map.insert(entry.key().to_string(), start_addr + len);
-            len += entry.value().len() as u32;
+            len = len.next_multiple_of(entry.align());
+            offsets.push(len);
+            len += entry.len() as u32;
+        }
+
+        offsets
+    }
+
+    pub fn addresses(&self, start_addr: u32) -> HashMap<String, u32> {
+        self.data
+            .iter()
+            .zip(self.offsets())
+            .map(|(entry, offset)| (entry.key().to_string(), start_addr + offset))
+            .collect()
+    }
+
+    /// Resolves the labels referenced by the entries, such as jump tables.
+    ///
+    /// `labels` holds the absolute address of each label while `start_addr` is the address of
+    /// this section.
+    ///
+    /// # Errors
+    ///
+    /// See [`DataEntry::backpatch`].
+    pub fn backpatch(
+        &mut self,
+        labels: &HashMap<String, i64>,
+        start_addr: u32,
+    ) -> Result<(), AsmError> {
+        let offsets = self.offsets();
+        for (entry, offset) in self.data.iter_mut().zip(offsets) {
+            entry.backpatch(labels, start_addr + offset)?;
         }
 
-        map
+        Ok(())
     }
 }
 
 impl AsBytes for DataSection {
     fn as_bytes(&self) -> Vec<u8> {
-        self.data.iter().flat_map(AsBytes::as_bytes).collect()
+        let mut bytes = Vec::new();
+        for (entry, offset) in self.data.iter().zip(self.offsets()) {
+            bytes.resize(offset as usize, 0);
+            bytes.append(&mut entry.as_bytes());
+        }
+
+        bytes
     }
 }
 
@@ -42,9 +136,15 @@ impl AsAsm for DataSection {
             return String::new();
         }
 
-        let mut out: String = "section .data\n".into();
+        let mut out = format!(
+            "section {}\n",
+            if self.read_only { ".rodata" } else { ".data" }
+        );
 
         for d in &self.data {
+            if d.align() > 1 {
+                out += &format!("    align {}, db 0\n", d.align());
+            }
             out += &format!("    {}\n", d.as_asm())
         }
 
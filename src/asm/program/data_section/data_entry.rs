@@ -1,39 +1,349 @@
-use crate::{asm::AsAsm, prelude::AsBytes};
+use std::collections::HashMap;
+
+use crate::{
+    asm::{unsupported, AsAsm, AsmError, Size},
+    prelude::AsBytes,
+};
 
 #[derive(Clone)]
 pub struct DataEntry {
     key: String,
-    value: String,
+    value: DataValue,
+    /// The address of this entry is a multiple of `align`, padded with zeros.
+    align: u32,
+}
+
+/// The content of a [`DataEntry`].
+///
+/// Scalars are arrays of a single value, see the [`From`] implementations.
+#[derive(Clone)]
+pub enum DataValue {
+    /// A string, without terminating NUL byte, e.g. `db "hello"`.
+    String(String),
+    /// A NUL-terminated string, e.g. `db "hello", 0`.
+    CString(String),
+    /// Raw bytes, e.g. `db 1, 2, 3`.
+    Bytes(Vec<u8>),
+    /// 16 bits values, e.g. `dw 1, 2, 3`.
+    Words(Vec<u16>),
+    /// 32 bits values, e.g. `dd 1, 2, 3`.
+    Dwords(Vec<u32>),
+    /// 64 bits values, e.g. `dq 1, 2, 3`.
+    Qwords(Vec<u64>),
+    /// The same value repeated `count` times, e.g. `times 16 dq 0`.
+    ///
+    /// `value` is truncated to `size`.
+    Fill {
+        size: Size,
+        count: usize,
+        value: u64,
+    },
+    /// Absolute addresses of labels, each stored on 64 bits, e.g. `dq case1, case2`.
+    ///
+    /// Addresses are resolved during backpatch.
+    Addresses(Vec<(String, u64)>),
+    /// Offsets of labels from the start of the entry, each stored on 32 bits, e.g.
+    /// `dd case1 - table, case2 - table`.
+    ///
+    /// Offsets are resolved during backpatch.
+    Offsets(Vec<(String, i32)>),
 }
 
 impl DataEntry {
-    pub fn new(key: String, value: String) -> Self {
-        Self { key, value }
+    pub fn new(key: String, value: DataValue) -> Self {
+        Self {
+            key,
+            value,
+            align: 1,
+        }
+    }
+
+    /// Aligns this entry on a multiple of `align`.
+    pub fn with_align(mut self, align: u32) -> Self {
+        self.align = align;
+        self
     }
 
     pub fn key(&self) -> &str {
         self.key.as_ref()
     }
 
-    pub fn value(&self) -> &str {
-        self.value.as_ref()
+    /// The alignment of this entry, `1` if the one set is not a power of two as reported by
+    /// [`DataEntry::backpatch`].
+    pub fn align(&self) -> u32 {
+        if self.align.is_power_of_two() {
+            self.align
+        } else {
+            1
+        }
+    }
+
+    /// The size in bytes of this entry.
+    pub fn len(&self) -> usize {
+        match &self.value {
+            DataValue::String(s) => s.len(),
+            DataValue::CString(s) => s.len() + 1,
+            DataValue::Bytes(values) => values.len(),
+            DataValue::Words(values) => values.len() * 2,
+            DataValue::Dwords(values) => values.len() * 4,
+            DataValue::Qwords(values) => values.len() * 8,
+            DataValue::Fill { size, count, .. } => size.bytes() as usize * count,
+            DataValue::Addresses(labels) => labels.len() * 8,
+            DataValue::Offsets(labels) => labels.len() * 4,
+        }
+    }
+
+    /// The labels this entry refers to, such as the targets of a jump table.
+    pub fn labels(&self) -> Vec<&str> {
+        match &self.value {
+            DataValue::Addresses(entries) => entries.iter().map(|(l, _)| l.as_str()).collect(),
+            DataValue::Offsets(entries) => entries.iter().map(|(l, _)| l.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Resolves the labels this entry refers to, `addr` being the address of this entry.
+    ///
+    /// # Errors
+    ///
+    /// - [`AsmError::UndefinedLabel`] if a label is not in `labels`.
+    /// - [`AsmError::DisplacementOverflow`] if an offset does not fit in 32 bits.
+    /// - [`AsmError::UnsupportedOperands`] if the alignment is not a power of two.
+    pub fn backpatch(&mut self, labels: &HashMap<String, i64>, addr: u32) -> Result<(), AsmError> {
+        if !self.align.is_power_of_two() {
+            return unsupported(format!(
+                "alignment {} of data '{}' is not a power of two",
+                self.align, self.key
+            ));
+        }
+
+        let resolve = |label: &str| {
+            labels
+                .get(label)
+                .copied()
+                .ok_or_else(|| AsmError::UndefinedLabel(label.into()))
+        };
+
+        match &mut self.value {
+            DataValue::Addresses(entries) => {
+                for (label, label_addr) in entries {
+                    *label_addr = resolve(label)? as u64;
+                }
+            }
+            DataValue::Offsets(entries) => {
+                for (label, offset) in entries {
+                    let value = resolve(label)? - addr as i64;
+                    *offset = i32::try_from(value).map_err(|_| AsmError::DisplacementOverflow {
+                        label: label.clone(),
+                        value,
+                    })?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Joins displayable values with commas, as expected by data directives.
+fn list<T: ToString>(values: impl IntoIterator<Item = T>) -> String {
+    values
+        .into_iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats bytes as the operands of a `db` directive, assembling to exactly these bytes.
+///
+/// Printable ASCII characters are grouped in quoted runs while other bytes, including `"`, are
+/// written as numbers, e.g. `"say ", 34, "hi", 34, 10`.
+fn string_asm(bytes: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut run = String::new();
+
+    for &byte in bytes {
+        if (b' '..=b'~').contains(&byte) && byte != b'"' {
+            run.push(byte as char);
+            continue;
+        }
+
+        if !run.is_empty() {
+            parts.push(format!(r#""{run}""#));
+            run.clear();
+        }
+        parts.push(byte.to_string());
+    }
+
+    if !run.is_empty() {
+        parts.push(format!(r#""{run}""#));
     }
+
+    parts.join(", ")
 }
 
 impl AsBytes for DataEntry {
     fn as_bytes(&self) -> Vec<u8> {
-        self.value.as_bytes().to_vec()
+        match &self.value {
+            DataValue::String(s) => s.as_bytes().to_vec(),
+            DataValue::CString(s) => {
+                let mut bytes = s.as_bytes().to_vec();
+                bytes.push(0);
+                bytes
+            }
+            DataValue::Bytes(values) => values.clone(),
+            DataValue::Words(values) => values.iter().flat_map(AsBytes::as_bytes).collect(),
+            DataValue::Dwords(values) => values.iter().flat_map(AsBytes::as_bytes).collect(),
+            DataValue::Qwords(values) => values.iter().flat_map(AsBytes::as_bytes).collect(),
+            DataValue::Fill { size, count, value } => {
+                let bytes = &value.as_bytes()[..size.bytes() as usize];
+                bytes.repeat(*count)
+            }
+            DataValue::Addresses(entries) => {
+                entries.iter().flat_map(|(_, a)| a.as_bytes()).collect()
+            }
+            DataValue::Offsets(entries) => entries.iter().flat_map(|(_, o)| o.as_bytes()).collect(),
+        }
     }
 }
 
 impl AsAsm for DataEntry {
     fn as_asm(&self) -> String {
-        format!(
-            r#"{} db "{}""#,
-            self.key,
-            self.value
-                // TODO: other chars should be converted too
-                .replace('\n', &format!(r#"", {}, ""#, '\n' as u32))
-        )
+        // an empty entry only marks an address, e.g. the end of a string
+        if self.len() == 0 {
+            return format!("{}:", self.key);
+        }
+
+        let value = match &self.value {
+            DataValue::String(_) | DataValue::CString(_) => {
+                format!("db {}", string_asm(&self.as_bytes()))
+            }
+            DataValue::Bytes(values) => format!("db {}", list(values)),
+            DataValue::Words(values) => format!("dw {}", list(values)),
+            DataValue::Dwords(values) => format!("dd {}", list(values)),
+            DataValue::Qwords(values) => format!("dq {}", list(values)),
+            DataValue::Fill { size, count, value } => {
+                let mask = u64::MAX >> (64 - 8 * size.bytes() as u32);
+                format!("times {count} {} {}", size.directive(), value & mask)
+            }
+            DataValue::Addresses(entries) => {
+                format!("dq {}", list(entries.iter().map(|(label, _)| label)))
+            }
+            DataValue::Offsets(entries) => format!(
+                "dd {}",
+                list(
+                    entries
+                        .iter()
+                        .map(|(label, _)| format!("{label} - {}", self.key))
+                )
+            ),
+        };
+
+        format!("{} {value}", self.key)
+    }
+}
+
+/// Implements [`From`] for scalars and arrays of the given type.
+macro_rules! impl_from_values {
+    ($ty:ty, $variant:ident) => {
+        impl From<$ty> for DataValue {
+            fn from(value: $ty) -> Self {
+                DataValue::$variant(vec![value])
+            }
+        }
+
+        impl From<Vec<$ty>> for DataValue {
+            fn from(values: Vec<$ty>) -> Self {
+                DataValue::$variant(values)
+            }
+        }
+
+        impl From<&[$ty]> for DataValue {
+            fn from(values: &[$ty]) -> Self {
+                DataValue::$variant(values.to_vec())
+            }
+        }
+    };
+}
+
+impl_from_values!(u8, Bytes);
+impl_from_values!(u16, Words);
+impl_from_values!(u32, Dwords);
+impl_from_values!(u64, Qwords);
+
+impl From<&str> for DataValue {
+    fn from(value: &str) -> Self {
+        DataValue::String(value.into())
+    }
+}
+
+impl From<String> for DataValue {
+    fn from(value: String) -> Self {
+        DataValue::String(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: impl Into<DataValue>) -> DataEntry {
+        DataEntry::new("x".into(), value.into())
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(entry(0x12u8).as_bytes(), vec![0x12]);
+        assert_eq!(entry(0x1234u16).as_bytes(), vec![0x34, 0x12]);
+        assert_eq!(entry(1u32).as_bytes(), vec![1, 0, 0, 0]);
+        assert_eq!(entry(1u64).as_bytes(), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(entry(1u64).as_asm(), "x dq 1");
+    }
+
+    #[test]
+    fn arrays() {
+        let words = entry(vec![1u16, 2, 3]);
+        assert_eq!(words.len(), 6);
+        assert_eq!(words.as_bytes(), vec![1, 0, 2, 0, 3, 0]);
+        assert_eq!(words.as_asm(), "x dw 1, 2, 3");
+
+        assert_eq!(entry([0xFFu8, 0].as_slice()).as_asm(), "x db 255, 0");
+    }
+
+    #[test]
+    fn strings() {
+        let cstring = entry(DataValue::CString("ab".into()));
+        assert_eq!(cstring.as_bytes(), vec![b'a', b'b', 0]);
+        assert_eq!(cstring.as_asm(), r#"x db "ab", 0"#);
+
+        assert_eq!(entry("ab").as_bytes(), vec![b'a', b'b']);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(entry("").as_asm(), "x:");
+        assert_eq!(entry("a\n").as_asm(), r#"x db "a", 10"#);
+        assert_eq!(
+            entry("say \"hi\"\tnow").as_asm(),
+            r#"x db "say ", 34, "hi", 34, 9, "now""#
+        );
+        assert_eq!(
+            entry(DataValue::CString("é\\".into())).as_asm(),
+            r#"x db 195, 169, "\", 0"#
+        );
+    }
+
+    #[test]
+    fn fill() {
+        let fill = entry(DataValue::Fill {
+            size: Size::Word,
+            count: 3,
+            value: 0x10203,
+        });
+
+        assert_eq!(fill.len(), 6);
+        assert_eq!(fill.as_bytes(), vec![3, 2, 3, 2, 3, 2]);
+        assert_eq!(fill.as_asm(), "x times 3 dw 515");
     }
 }
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use super::Program;
+use crate::asm::{
+    LabelId, Memory,
+    Mnemonic::{self, *},
+    Operand,
+    Register::{self, *},
+    Size,
+};
+
+/// Callee-saved registers which may be written by a function, in the order they are saved.
+const SAVED: [Register; 5] = [Rbx, R12, R13, R14, R15];
+
+/// A function following the System V calling convention, emitted with [`Program::function`].
+///
+/// ```
+/// # use tiny_elf::asm::{Function, Mnemonic::*, Program, Register::*, Size};
+/// // long sum(long a, long b) { long total = a + b; return total; }
+/// let sum = Function::new("sum")
+///     .param("a")
+///     .param("b")
+///     .local("total", Size::Qword);
+///
+/// // rbx is saved as it is written
+/// let program = Program::default().function(sum, |body, frame| {
+///     let total = frame.local("total").unwrap();
+///     body.add(Mov(Rbx, frame.param("a").unwrap()))
+///         .add(Add(Rbx, frame.param("b").unwrap()))
+///         .add(Store(total.clone(), Rbx.into()))
+///         .add(Mov(Rax, total.into()))
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    locals: Vec<(String, Size)>,
+    saved: Vec<Register>,
+}
+
+/// The stack frame of a [`Function`], given to its body to access parameters and locals.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    params: HashMap<String, Operand>,
+    locals: HashMap<String, Memory>,
+    ret: LabelId,
+}
+
+impl Function {
+    /// Creates a function, `name` being its label.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+            locals: Vec::new(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Declares the next 64 bits integer parameter.
+    ///
+    /// The first six are passed in [`Register::ARGUMENTS`], the following ones on the stack.
+    pub fn param(mut self, name: &str) -> Self {
+        self.params.push(name.into());
+        self
+    }
+
+    /// Declares a local variable, stored in the stack frame, e.g. `[rbp - 8]`.
+    pub fn local(mut self, name: &str, size: Size) -> Self {
+        self.locals.push((name.into(), size));
+        self
+    }
+
+    /// Saves a register, restoring it on return.
+    ///
+    /// Callee-saved registers written by the body are saved without being declared, see
+    /// [`Program::function`]. Useful for a register written in a way not detected, e.g. by a
+    /// called routine breaking the convention.
+    ///
+    /// [`Rsp`] and [`Rbp`] are always restored, so ignored. A register that can't be pushed, e.g.
+    /// `eax`, is reported when encoding.
+    pub fn save(mut self, register: Register) -> Self {
+        if !matches!(register.with_size(Size::Qword), Rsp | Rbp) && !self.saved.contains(&register)
+        {
+            self.saved.push(register);
+        }
+        self
+    }
+
+    /// Computes the frame of this function and the size of its locals.
+    ///
+    /// Locals are stored right below [`Rbp`], aligned on their size. Saved registers are pushed
+    /// below them, so that their offsets don't depend on the registers written by the body.
+    fn frame(&self, ret: LabelId) -> (Frame, u32) {
+        let params = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let operand = match Register::ARGUMENTS.get(i) {
+                    Some(r) => (*r).into(),
+                    // above the saved rbp and the return address
+                    None => Memory::base(Rbp)
+                        .disp(16 + 8 * (i - Register::ARGUMENTS.len()) as i32)
+                        .with_size(Size::Qword)
+                        .into(),
+                };
+                (name.clone(), operand)
+            })
+            .collect();
+
+        let mut len = 0;
+        let locals = self
+            .locals
+            .iter()
+            .map(|(name, size)| {
+                let bytes = size.bytes() as u32;
+                len = (len + bytes).next_multiple_of(bytes);
+                let mem = Memory::base(Rbp).disp(-(len as i32)).with_size(*size);
+                (name.clone(), mem)
+            })
+            .collect();
+
+        (
+            Frame {
+                params,
+                locals,
+                ret,
+            },
+            len,
+        )
+    }
+}
+
+impl Frame {
+    /// The register or stack slot holding a parameter, if declared, see [`Function::param`].
+    pub fn param(&self, name: &str) -> Option<Operand> {
+        self.params.get(name).cloned()
+    }
+
+    /// The stack slot of a local variable, if declared, see [`Function::local`].
+    pub fn local(&self, name: &str) -> Option<Memory> {
+        self.locals.get(name).cloned()
+    }
+
+    /// The epilogue of the function, to jump to for an early return.
+    pub fn ret(&self) -> Memory {
+        (&self.ret).into()
+    }
+}
+
+impl Program {
+    /// Emits a function: its prologue, `body`, then its epilogue returning to the caller.
+    ///
+    /// The value returned in [`Rax`] must be set by `body`. Callee-saved registers written by
+    /// `body`, along with the ones declared with [`Function::save`], are saved in the prologue
+    /// and restored in the epilogue. The reserved stack keeps [`Rsp`] aligned on 16 bytes, as
+    /// expected at call sites.
+    pub fn function(
+        mut self,
+        function: Function,
+        body: impl FnOnce(Program, &Frame) -> Program,
+    ) -> Self {
+        let ret = self.new_label(&format!("{}_return", function.name));
+        let (frame, locals_len) = function.frame(ret);
+
+        let this = self
+            .label(&function.name)
+            .add(Push(Rbp.into()))
+            .add(Mov(Rbp, Rsp.into()));
+        let start = this.instructions.len();
+        let mut this = body(this, &frame);
+
+        let written: Vec<_> = this.instructions[start..]
+            .iter()
+            .filter_map(Mnemonic::destination)
+            .map(|r| r.with_size(Size::Qword))
+            .collect();
+        let mut saved: Vec<_> = SAVED
+            .into_iter()
+            .filter(|r| function.saved.contains(r) || written.contains(r))
+            .collect();
+        saved.extend(function.saved.iter().filter(|r| !SAVED.contains(r)));
+
+        let saved_len = 8 * saved.len() as u32;
+        let reserved = (locals_len + saved_len).next_multiple_of(16) - saved_len;
+        let mut prologue = Vec::new();
+        if reserved > 0 {
+            prologue.push(Sub(Rsp, (reserved as i32).into()));
+        }
+        prologue.extend(saved.iter().map(|r| Push((*r).into())));
+        this = this.insert(start, prologue).label(&frame.ret);
+
+        if !saved.is_empty() {
+            let pushed_len = (reserved + saved_len) as i32;
+            this = this.add(Lea(Rsp, Memory::base(Rbp).disp(-pushed_len)));
+        }
+        for r in saved.iter().rev() {
+            this = this.add(Pop(*r));
+        }
+
+        // above the locals
+        this.add(Mov(Rsp, Rbp.into())).add(Pop(Rbp)).add(Ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{AsAsm, AsmError, Location};
+
+    #[test]
+    fn frame() {
+        let function = Function::new("f")
+            .param("a")
+            .param("b")
+            .param("c")
+            .param("d")
+            .param("e")
+            .param("f")
+            .param("g")
+            .local("flag", Size::Byte)
+            .local("count", Size::Qword)
+            .save(Rbx);
+
+        let program = Program::default().function(function, |body, frame| {
+            assert_eq!(frame.param("a").unwrap().as_asm(), "rdi");
+            assert_eq!(frame.param("f").unwrap().as_asm(), "r9");
+            assert!(frame.param("h").is_none());
+            assert_eq!(
+                Mov(Rax, frame.param("g").unwrap()).as_asm(),
+                "mov rax, qword [rbp + 16]"
+            );
+            let local = |name| frame.local(name).unwrap().as_asm_effective();
+            assert_eq!(local("flag"), "byte [rbp - 1]");
+            assert_eq!(local("count"), "qword [rbp - 16]");
+
+            // saved without being declared
+            body.add(Xor(R12d, R12d.into())).add(Jmp(frame.ret()))
+        });
+
+        let asm: Vec<_> = program.instructions.iter().map(AsAsm::as_asm).collect();
+        assert_eq!(
+            asm,
+            [
+                "\nf:",
+                "push rbp",
+                "mov rbp, rsp",
+                // 16 bytes of locals then 16 of saved registers
+                "sub rsp, 16",
+                "push rbx",
+                "push r12",
+                "xor r12d, r12d",
+                "jmp ..@f_return_0",
+                "\n..@f_return_0:",
+                "lea rsp, [rbp - 32]",
+                "pop r12",
+                "pop rbx",
+                "mov rsp, rbp",
+                "pop rbp",
+                "ret",
+            ]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let program = Program::default()
+            .function(Function::new("f").local("x", Size::Qword), |body, _| {
+                body.add(Add(Rbx, Eax.into())).span(3..8)
+            });
+
+        let Err(AsmError::Encoding(diagnostics)) = program.check_encoding() else {
+            panic!("expected an encoding error");
+        };
+        assert_eq!(
+            diagnostics[0].location,
+            // after the reserved stack and the push of rbx
+            Location::Instruction {
+                index: 5,
+                span: Some(3..8)
+            }
+        );
+    }
+
+    #[test]
+    fn save() {
+        let function = Function::new("f").save(R10).save(Rbp).save(Rbx);
+        let program = Program::default().function(function, |body, _| body);
+
+        let asm: Vec<_> = program.instructions.iter().map(AsAsm::as_asm).collect();
+        assert_eq!(asm[3..5], ["push rbx", "push r10"]);
+        assert_eq!(asm.iter().filter(|i| *i == "push rbp").count(), 1);
+
+        let program = Program::default().function(Function::new("f").save(Eax), |body, _| body);
+        assert!(matches!(
+            program.check_encoding(),
+            Err(AsmError::Encoding(_))
+        ));
+    }
+}
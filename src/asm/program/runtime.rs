@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+
+use super::Program;
+use crate::asm::{
+    syscalls::{LinuxSyscall, STDIN, STDOUT},
+    Condition, Memory,
+    Mnemonic::*,
+    Operand,
+    Register::*,
+    Scale, Size,
+};
+
+/// A routine of the runtime library, called with [`Call`](crate::asm::Mnemonic::Call) and
+/// emitted by [`Program::link_runtime`].
+///
+/// Routines follow the System V calling convention: arguments are passed in
+/// [`Register::ARGUMENTS`](crate::asm::Register::ARGUMENTS), the result is returned in [`Rax`]
+/// and caller-saved registers may be overwritten.
+///
+/// ```
+/// # use tiny_elf::asm::{syscalls::LinuxSyscall, Mnemonic::*, Program, Register::*, Routine};
+/// // PRINT 42
+/// let program = Program::default()
+///     .add(Mov(Rdi, 42.into()))
+///     .add(Call(Routine::PrintInt.into()))
+///     .add(Call(Routine::PrintNewline.into()))
+///     .syscall(LinuxSyscall::Exit { code: 0.into() })
+///     .link_runtime();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routine {
+    /// Prints the signed integer in [`Rdi`] in decimal to the standard output.
+    PrintInt,
+    /// Prints the [`Rsi`] bytes pointed to by [`Rdi`] to the standard output.
+    PrintStr,
+    /// Prints a line feed to the standard output.
+    PrintNewline,
+    /// Reads a line from the standard input in the buffer pointed to by [`Rdi`] of [`Rsi`]
+    /// bytes, returning its length without the line feed.
+    ///
+    /// Reading stops after a line feed, at the end of the input or once the buffer is full.
+    ReadLine,
+    /// Parses the signed decimal integer in the [`Rsi`] bytes pointed to by [`Rdi`].
+    ///
+    /// Leading spaces and a sign are skipped, parsing stops at the first other character.
+    ParseInt,
+}
+
+impl Routine {
+    /// Every routine, in the order they are linked.
+    pub const ALL: [Routine; 5] = [
+        Routine::PrintInt,
+        Routine::PrintStr,
+        Routine::PrintNewline,
+        Routine::ReadLine,
+        Routine::ParseInt,
+    ];
+
+    /// The label of this routine, to call.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Routine::PrintInt => "rt_print_int",
+            Routine::PrintStr => "rt_print_str",
+            Routine::PrintNewline => "rt_print_newline",
+            Routine::ReadLine => "rt_read_line",
+            Routine::ParseInt => "rt_parse_int",
+        }
+    }
+
+    /// Emits the instructions of this routine, starting with its label.
+    fn emit(self, program: Program) -> Program {
+        match self {
+            Routine::PrintInt => program
+                .func(self.label())
+                // 20 digits and a sign at most, written backward from rbp
+                .add(Sub(Rsp, 32.into()))
+                .add(Mov(Rax, Rdi.into()))
+                .add(Mov(Rsi, Rbp.into()))
+                .add(Mov(Rcx, 10.into()))
+                .add(Cmp(Rax, 0.into()))
+                .add(Jge(".digit".into()))
+                // the negation of i64::MIN is itself, still right as an unsigned dividend
+                .add(Xor(Edx, Edx.into()))
+                .add(Sub(Rdx, Rax.into()))
+                .add(Mov(Rax, Rdx.into()))
+                .label(".digit")
+                .add(Xor(Edx, Edx.into()))
+                .add(Div(Rcx))
+                .add(Add(Edx, (b'0' as i32).into()))
+                .add(Dec(Rsi))
+                .add(Store(Memory::base(Rsi), Dl.into()))
+                .add(Cmp(Rax, 0.into()))
+                .add(Jne(".digit".into()))
+                .add(Cmp(Rdi, 0.into()))
+                .add(Jge(".write".into()))
+                .add(Dec(Rsi))
+                .add(Store(
+                    Memory::base(Rsi).with_size(Size::Byte),
+                    (b'-' as i32).into(),
+                ))
+                .label(".write")
+                .add(Mov(Rdx, Rbp.into()))
+                .add(Sub(Rdx, Rsi.into()))
+                .syscall(LinuxSyscall::Write {
+                    fd: STDOUT.into(),
+                    buf: Rsi.into(),
+                    count: Rdx.into(),
+                })
+                .func_end(),
+            Routine::PrintStr => program
+                .label(self.label())
+                .add(Mov(Rdx, Rsi.into()))
+                .add(Mov(Rsi, Rdi.into()))
+                .syscall(LinuxSyscall::Write {
+                    fd: STDOUT.into(),
+                    buf: Rsi.into(),
+                    count: Rdx.into(),
+                })
+                .add(Ret),
+            Routine::PrintNewline => program
+                .label(self.label())
+                .add(Push((b'\n' as i32).into()))
+                .add(Mov(Rsi, Rsp.into()))
+                .syscall(LinuxSyscall::Write {
+                    fd: STDOUT.into(),
+                    buf: Rsi.into(),
+                    count: 1.into(),
+                })
+                .add(Add(Rsp, 8.into()))
+                .add(Ret),
+            // reads one byte at a time, not to consume the following lines
+            Routine::ReadLine => program
+                .label(self.label())
+                .add(Mov(R8, Rdi.into()))
+                .add(Mov(R9, Rsi.into()))
+                .add(Xor(R10d, R10d.into()))
+                .label(".read")
+                .add(Cmp(R10, R9.into()))
+                .add(Jge(".end".into()))
+                .add(Lea(Rsi, Memory::base(R8).index(R10, Scale::One)))
+                .syscall(LinuxSyscall::Read {
+                    fd: STDIN.into(),
+                    buf: Rsi.into(),
+                    count: 1.into(),
+                })
+                .add(Cmp(Rax, 1.into()))
+                .add(Jne(".end".into()))
+                .add(Movzx(
+                    Eax,
+                    Memory::base(R8)
+                        .index(R10, Scale::One)
+                        .with_size(Size::Byte)
+                        .into(),
+                ))
+                .add(Cmp(Eax, (b'\n' as i32).into()))
+                .add(Je(".end".into()))
+                .add(Inc(R10))
+                .add(Jmp(".read".into()))
+                .label(".end")
+                .add(Mov(Rax, R10.into()))
+                .add(Ret),
+            Routine::ParseInt => {
+                let byte = || {
+                    Memory::base(Rdi)
+                        .index(Rcx, Scale::One)
+                        .with_size(Size::Byte)
+                        .into()
+                };
+
+                program
+                    .label(self.label())
+                    .add(Xor(Eax, Eax.into()))
+                    .add(Xor(Ecx, Ecx.into()))
+                    .add(Xor(R8d, R8d.into()))
+                    .label(".space")
+                    .add(Cmp(Rcx, Rsi.into()))
+                    .add(Jge(".done".into()))
+                    .add(Movzx(Edx, byte()))
+                    .add(Cmp(Edx, (b' ' as i32).into()))
+                    .add(Jne(".sign".into()))
+                    .add(Inc(Rcx))
+                    .add(Jmp(".space".into()))
+                    .label(".sign")
+                    .add(Cmp(Edx, (b'+' as i32).into()))
+                    .add(Je(".skip_sign".into()))
+                    .add(Cmp(Edx, (b'-' as i32).into()))
+                    .add(Jne(".digit".into()))
+                    .add(Mov(R8d, 1.into()))
+                    .label(".skip_sign")
+                    .add(Inc(Rcx))
+                    .label(".digit")
+                    .add(Cmp(Rcx, Rsi.into()))
+                    .add(Jge(".negate".into()))
+                    .add(Movzx(Edx, byte()))
+                    // characters below '0' wrap around, above 9 as well
+                    .add(Sub(Edx, (b'0' as i32).into()))
+                    .add(Cmp(Edx, 9.into()))
+                    .add(Jcc(Condition::A, ".negate".into()))
+                    .add(IMul(Rax, 10.into()))
+                    .add(Add(Rax, Rdx.into()))
+                    .add(Inc(Rcx))
+                    .add(Jmp(".digit".into()))
+                    .label(".negate")
+                    .add(Cmp(R8d, 0.into()))
+                    .add(Je(".done".into()))
+                    .add(Xor(Edx, Edx.into()))
+                    .add(Sub(Rdx, Rax.into()))
+                    .add(Mov(Rax, Rdx.into()))
+                    .label(".done")
+                    .add(Ret)
+            }
+        }
+    }
+}
+
+impl From<Routine> for Memory {
+    fn from(routine: Routine) -> Self {
+        routine.label().into()
+    }
+}
+
+impl Program {
+    /// Emits the [runtime routines](Routine) referenced by the program, and only those.
+    ///
+    /// A routine is referenced by a call, a jump, an address (e.g. `lea rax, [rel rt_print]`)
+    /// or an expression. To be done once every reference is added, usually at the end of the
+    /// program. Routines already linked are not emitted again.
+    pub fn link_runtime(self) -> Self {
+        let mut referenced = HashSet::new();
+        let mut defined = HashSet::new();
+        for inst in &self.instructions {
+            if let Label(label) = inst {
+                defined.insert(label.clone());
+            }
+
+            let mut inst = inst.clone();
+            if let Some(target) = inst.branch_target_mut() {
+                referenced.insert(target.label().to_owned());
+            }
+            if let Some(mem) = inst.memory_operand_mut() {
+                referenced.insert(mem.label().to_owned());
+            }
+            if let Some(Operand::Expr(expr)) = inst.immediate_operand_mut() {
+                referenced.extend(expr.symbols().into_iter().map(str::to_owned));
+            }
+        }
+
+        Routine::ALL
+            .into_iter()
+            .filter(|r| referenced.contains(r.label()) && !defined.contains(r.label()))
+            .fold(self, |program, routine| routine.emit(program))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::{AsAsm, Mnemonic},
+        Elf,
+    };
+
+    #[test]
+    fn link_runtime() {
+        let program = Program::default()
+            .add(Call(Routine::PrintNewline.into()))
+            .add(Call(Routine::PrintNewline.into()))
+            .link_runtime()
+            .link_runtime();
+
+        let labels: Vec<_> = program
+            .instructions
+            .iter()
+            .filter(|inst| matches!(inst, Mnemonic::Label(_)))
+            .map(AsAsm::as_asm)
+            .collect();
+        assert_eq!(labels, ["\nrt_print_newline:"]);
+
+        // referenced without being called
+        let program = Program::default()
+            .add(Jmp(Routine::PrintNewline.into()))
+            .add(Lea(Rax, Routine::PrintInt.into()))
+            .link_runtime();
+        assert!(program.check_labels().is_ok());
+    }
+
+    /// Builds `program` into an executable, runs it with `stdin` and returns its output.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn run(name: &str, program: Program, stdin: &str) -> String {
+        use std::{
+            io::Write,
+            os::unix::fs::PermissionsExt,
+            process::{Command, Stdio},
+        };
+
+        let program = program
+            .syscall(LinuxSyscall::Exit { code: 0.into() })
+            .link_runtime();
+        let mut elf = Elf::new(program);
+        elf.backpatch().unwrap();
+
+        let path = std::env::temp_dir().join(format!("tiny_elf_{name}_{}", std::process::id()));
+        std::fs::write(&path, elf.encode().unwrap()).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn print() {
+        let mut program = Program::default().insert_rodata("msg", "X = ");
+        for n in [0, 42, -7, i64::MIN] {
+            program = program
+                .add(Lea(Rdi, "msg".into()))
+                .add(Mov(Rsi, 4.into()))
+                .add(Call(Routine::PrintStr.into()))
+                .add(Mov(Rdi, n.into()))
+                .add(Call(Routine::PrintInt.into()))
+                .add(Call(Routine::PrintNewline.into()));
+        }
+
+        assert_eq!(
+            run("print", program, ""),
+            "X = 0\nX = 42\nX = -7\nX = -9223372036854775808\n"
+        );
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn input() {
+        // prints twice each line read, until an empty one
+        let program = Program::default()
+            .insert_data("buffer", [0u8; 16].as_slice())
+            .label("input")
+            .add(Lea(Rdi, "buffer".into()))
+            .add(Mov(Rsi, 16.into()))
+            .add(Call(Routine::ReadLine.into()))
+            .add(Cmp(Rax, 0.into()))
+            .add(Je("end".into()))
+            .add(Lea(Rdi, "buffer".into()))
+            .add(Mov(Rsi, Rax.into()))
+            .add(Call(Routine::ParseInt.into()))
+            .add(Add(Rax, Rax.into()))
+            .add(Mov(Rdi, Rax.into()))
+            .add(Call(Routine::PrintInt.into()))
+            .add(Call(Routine::PrintNewline.into()))
+            .add(Jmp("input".into()))
+            .label("end");
+
+        assert_eq!(
+            run("input", program, "21\n  -5\n+3x\nabc\n\n12\n"),
+            "42\n-10\n6\n0\n"
+        );
+    }
+}
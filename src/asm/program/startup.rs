@@ -0,0 +1,126 @@
+use super::Program;
+use crate::asm::{
+    AsAsm, Memory,
+    Mnemonic::*,
+    Register::{self, *},
+    Scale, Size,
+};
+
+/// Data entry holding the number of arguments, stored by [`Program::startup`].
+pub const ARGC: &str = "argc";
+/// Data entry holding the address of the arguments array, stored by [`Program::startup`].
+pub const ARGV: &str = "argv";
+/// Data entry holding the address of the environment array, stored by [`Program::startup`].
+pub const ENVP: &str = "envp";
+
+impl Program {
+    /// Captures the arguments and environment of the process, to be called first.
+    ///
+    /// The kernel starts the program with `argc` on top of the stack, followed by the `argv`
+    /// and `envp` null terminated arrays of string pointers. They are stored in the [`ARGC`],
+    /// [`ARGV`] and [`ENVP`] 64 bits data entries, and left in [`Rdi`], [`Rsi`] and [`Rdx`] as
+    /// for a C `main`.
+    ///
+    /// ```
+    /// # use tiny_elf::asm::{syscalls::LinuxSyscall, Program, Register::*};
+    /// // exits with the length of the first argument
+    /// let program = Program::default()
+    ///     .startup()
+    ///     .load_arg(Rsi, 1)
+    ///     .strlen(Rdi, Rsi)
+    ///     .syscall(LinuxSyscall::Exit { code: Rdi.into() });
+    /// ```
+    pub fn startup(self) -> Self {
+        self.align_data(8)
+            .insert_data(ARGC, 0u64)
+            .insert_data(ARGV, 0u64)
+            .insert_data(ENVP, 0u64)
+            .add(Comment("startup".into()))
+            .add(Mov(Rdi, Memory::base(Rsp).into()))
+            .add(Lea(Rsi, Memory::base(Rsp).disp(8)))
+            // envp follows the null pointer ending argv
+            .add(Lea(Rdx, Memory::base(Rsi).index(Rdi, Scale::Eight).disp(8)))
+            .add(Store(ARGC.into(), Rdi.into()))
+            .add(Store(ARGV.into(), Rsi.into()))
+            .add(Store(ENVP.into(), Rdx.into()))
+    }
+
+    /// Loads the address of the argument `index` in `dest`, `0` being the program name.
+    ///
+    /// The argument must exist, see [`ARGC`]. Needs [`Program::startup`].
+    pub fn load_arg(self, dest: Register, index: u32) -> Self {
+        self.add(Mov(dest, Memory::from(ARGV).with_size(Size::Qword).into()))
+            .add(Mov(dest, Memory::base(dest).disp(8 * index as i32).into()))
+    }
+
+    /// Computes the length of the null terminated string pointed to by `src` in `dest`.
+    ///
+    /// `dest` may be smaller than 64 bits, e.g. `eax`. Other registers are preserved, two of them
+    /// being temporarily pushed on the stack.
+    pub fn strlen(mut self, dest: Register, src: Register) -> Self {
+        let used = [dest, src].map(|r| r.with_size(Size::Qword));
+        let mut scratch = [Rax, Rcx, Rdx, Rsi]
+            .into_iter()
+            .filter(|r| !used.contains(r));
+        let (ptr, byte) = (scratch.next().unwrap(), scratch.next().unwrap());
+        let loop_label = self.new_label("strlen");
+
+        self.add(Comment(format!("strlen {}", src.as_asm())))
+            .add(Push(ptr.into()))
+            .add(Push(byte.into()))
+            .add(Mov(ptr, src.into()))
+            .label(&loop_label)
+            .add(Movzx(
+                byte.with_size(Size::Dword),
+                Memory::base(ptr).with_size(Size::Byte).into(),
+            ))
+            .add(Inc(ptr))
+            .add(Cmp(byte.with_size(Size::Dword), 0.into()))
+            .add(Jne((&loop_label).into()))
+            // `ptr` is past the null byte
+            .add(Sub(ptr, src.into()))
+            .add(Dec(ptr))
+            .add(Mov(dest, ptr.with_size(dest.size()).into()))
+            .add(Pop(byte))
+            .add(Pop(ptr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patchable::Patchable;
+
+    #[test]
+    fn startup() {
+        let mut program = Program::default().startup().load_arg(Rsi, 1);
+        program.backpatch(0x1000, &[0x2000]).unwrap();
+
+        assert_eq!(program.data().addresses(0x2000)[ENVP], 0x2010);
+        let asm: Vec<_> = program.instructions.iter().map(AsAsm::as_asm).collect();
+        assert_eq!(
+            asm[1..],
+            [
+                "mov rdi, [rsp]",
+                "lea rsi, [rsp + 8]",
+                "lea rdx, [rsi + rdi*8 + 8]",
+                "mov [rel argc], rdi",
+                "mov [rel argv], rsi",
+                "mov [rel envp], rdx",
+                "mov rsi, qword [rel argv]",
+                "mov rsi, [rsi + 8]",
+            ]
+        );
+    }
+
+    #[test]
+    fn strlen() {
+        let program = Program::default().strlen(Eax, Rdi);
+        assert!(program.check_encoding().is_ok());
+
+        let asm: Vec<_> = program.instructions.iter().map(AsAsm::as_asm).collect();
+        // rax isn't used as scratch as it holds the result
+        assert_eq!(asm[1..3], ["push rcx", "push rdx"]);
+        assert_eq!(asm[asm.len() - 3], "mov eax, ecx");
+    }
+}
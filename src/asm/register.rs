@@ -1,14 +1,21 @@
-use std::ops::Add;
-
-use super::AsAsm;
+use super::{AsAsm, Size};
 use crate::prelude::AsBytes;
 
 /// # See also
 /// - <https://wiki.osdev.org/X86-64_Instruction_Encoding#Registers> for detail on available
-/// registers and their memory representation.
+///   registers and their memory representation.
 /// - <https://en.wikibooks.org/wiki/X86_Assembly/X86_Architecture> especially for name
-/// explanation.
-#[derive(Debug, Clone, Copy)]
+///   explanation.
+///
+/// 64 bits registers come first, followed by their 32, 16 and 8 bits counterparts (e.g. [`Rax`],
+/// [`Eax`], [`Ax`] and [`Al`]). `ah`, `ch`, `dh` and `bh` are not available, as they can't be
+/// encoded along a REX prefix.
+///
+/// [`Rax`]: Register::Rax
+/// [`Eax`]: Register::Eax
+/// [`Ax`]: Register::Ax
+/// [`Al`]: Register::Al
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
     /// Primary accumulator.
     ///
@@ -44,18 +51,171 @@ pub enum Register {
     R13,
     R14,
     R15,
+    // 32 bits registers
+    Eax,
+    Ecx,
+    Edx,
+    Ebx,
+    Esp,
+    Ebp,
+    Esi,
+    Edi,
+    R8d,
+    R9d,
+    R10d,
+    R11d,
+    R12d,
+    R13d,
+    R14d,
+    R15d,
+    // 16 bits registers
+    Ax,
+    Cx,
+    Dx,
+    Bx,
+    Sp,
+    Bp,
+    Si,
+    Di,
+    R8w,
+    R9w,
+    R10w,
+    R11w,
+    R12w,
+    R13w,
+    R14w,
+    R15w,
+    // 8 bits registers
+    Al,
+    Cl,
+    Dl,
+    Bl,
+    Spl,
+    Bpl,
+    Sil,
+    Dil,
+    R8b,
+    R9b,
+    R10b,
+    R11b,
+    R12b,
+    R13b,
+    R14b,
+    R15b,
 }
 
 impl Register {
+    /// Registers holding the first integer arguments of a function call, in order, as defined
+    /// by the System V calling convention.
+    pub const ARGUMENTS: [Register; 6] = [
+        Register::Rdi,
+        Register::Rsi,
+        Register::Rdx,
+        Register::Rcx,
+        Register::R8,
+        Register::R9,
+    ];
+
+    /// Every register, ordered as declared.
+    const ALL: [Register; 64] = [
+        Register::Rax,
+        Register::Rcx,
+        Register::Rdx,
+        Register::Rbx,
+        Register::Rsp,
+        Register::Rbp,
+        Register::Rsi,
+        Register::Rdi,
+        Register::R8,
+        Register::R9,
+        Register::R10,
+        Register::R11,
+        Register::R12,
+        Register::R13,
+        Register::R14,
+        Register::R15,
+        Register::Eax,
+        Register::Ecx,
+        Register::Edx,
+        Register::Ebx,
+        Register::Esp,
+        Register::Ebp,
+        Register::Esi,
+        Register::Edi,
+        Register::R8d,
+        Register::R9d,
+        Register::R10d,
+        Register::R11d,
+        Register::R12d,
+        Register::R13d,
+        Register::R14d,
+        Register::R15d,
+        Register::Ax,
+        Register::Cx,
+        Register::Dx,
+        Register::Bx,
+        Register::Sp,
+        Register::Bp,
+        Register::Si,
+        Register::Di,
+        Register::R8w,
+        Register::R9w,
+        Register::R10w,
+        Register::R11w,
+        Register::R12w,
+        Register::R13w,
+        Register::R14w,
+        Register::R15w,
+        Register::Al,
+        Register::Cl,
+        Register::Dl,
+        Register::Bl,
+        Register::Spl,
+        Register::Bpl,
+        Register::Sil,
+        Register::Dil,
+        Register::R8b,
+        Register::R9b,
+        Register::R10b,
+        Register::R11b,
+        Register::R12b,
+        Register::R13b,
+        Register::R14b,
+        Register::R15b,
+    ];
+
     /// `op` is left-shifted (`<<`) because of the position it should be.
     ///
     /// See <https://wiki.osdev.org/X86-64_Instruction_Encoding#ModR.2FM>
     pub fn as_bytes_opcode_extend(&self, op: u8) -> Vec<u8> {
-        vec![self
-            .as_bytes()
-            .first()
-            .expect("Register is always referenced by at least a byte")
-            .add(op << 3)]
+        vec![0xC0 | self.code() | op << 3]
+    }
+
+    /// Determines if a called function must preserve this register, as defined by the System V
+    /// calling convention.
+    pub fn is_callee_saved(&self) -> bool {
+        matches!(
+            self.with_size(Size::Qword),
+            Register::Rbx
+                | Register::Rsp
+                | Register::Rbp
+                | Register::R12
+                | Register::R13
+                | Register::R14
+                | Register::R15
+        )
+    }
+
+    /// The 3 lowest bits identifying this register in ModR/M and SIB bytes.
+    ///
+    /// The fourth bit is given by the REX prefix, see [`Register::is_extended`].
+    pub fn code(&self) -> u8 {
+        self.number() & 0b111
+    }
+
+    /// The number of this register, from 0 to 15 whatever its size.
+    fn number(&self) -> u8 {
+        *self as u8 % 16
     }
 
     /// Determines if this register is an extended register.
@@ -63,31 +223,44 @@ impl Register {
     /// See
     /// <https://stackoverflow.com/questions/35379820/what-do-instruction-prefixes-mean-in-modern-x86>
     pub fn is_extended(&self) -> bool {
+        self.number() > 0b111
+    }
+
+    /// Determines if this register needs a REX prefix to be encoded, even an empty one.
+    ///
+    /// Without it, `spl`, `bpl`, `sil` and `dil` are respectively `ah`, `ch`, `dh` and `bh`.
+    pub fn needs_rex(&self) -> bool {
         use Register::*;
-        matches!(self, R8 | R9 | R10 | R11 | R12 | R13 | R14 | R15)
+        self.is_extended() || matches!(self, Spl | Bpl | Sil | Dil)
+    }
+
+    /// The size of this register.
+    pub fn size(&self) -> Size {
+        match *self as u8 / 16 {
+            0 => Size::Qword,
+            1 => Size::Dword,
+            2 => Size::Word,
+            _ => Size::Byte,
+        }
+    }
+
+    /// The same register with another size, e.g. [`Register::Eax`] for [`Register::Rax`] and
+    /// [`Size::Dword`].
+    pub fn with_size(&self, size: Size) -> Self {
+        let offset = match size {
+            Size::Qword => 0,
+            Size::Dword => 16,
+            Size::Word => 32,
+            Size::Byte => 48,
+        };
+
+        Self::ALL[offset + self.number() as usize]
     }
 }
 
 impl AsBytes for Register {
     fn as_bytes(&self) -> Vec<u8> {
-        match self {
-            Register::Rax => vec![0xC0],
-            Register::Rcx => vec![0xC1],
-            Register::Rdx => vec![0xC2],
-            Register::Rbx => vec![0xC3],
-            Register::Rsp => vec![0xC4],
-            Register::Rbp => vec![0xC5],
-            Register::Rsi => vec![0xC6],
-            Register::Rdi => vec![0xC7],
-            Register::R8 => vec![0xC0],
-            Register::R9 => vec![0xC1],
-            Register::R10 => vec![0xC2],
-            Register::R11 => vec![0xC3],
-            Register::R12 => vec![0xC4],
-            Register::R13 => vec![0xC5],
-            Register::R14 => vec![0xC6],
-            Register::R15 => vec![0xC7],
-        }
+        vec![0xC0 | self.code()]
     }
 }
 
@@ -110,6 +283,54 @@ impl AsAsm for Register {
             Register::R13 => "r13",
             Register::R14 => "r14",
             Register::R15 => "r15",
+            Register::Eax => "eax",
+            Register::Ecx => "ecx",
+            Register::Edx => "edx",
+            Register::Ebx => "ebx",
+            Register::Esp => "esp",
+            Register::Ebp => "ebp",
+            Register::Esi => "esi",
+            Register::Edi => "edi",
+            Register::R8d => "r8d",
+            Register::R9d => "r9d",
+            Register::R10d => "r10d",
+            Register::R11d => "r11d",
+            Register::R12d => "r12d",
+            Register::R13d => "r13d",
+            Register::R14d => "r14d",
+            Register::R15d => "r15d",
+            Register::Ax => "ax",
+            Register::Cx => "cx",
+            Register::Dx => "dx",
+            Register::Bx => "bx",
+            Register::Sp => "sp",
+            Register::Bp => "bp",
+            Register::Si => "si",
+            Register::Di => "di",
+            Register::R8w => "r8w",
+            Register::R9w => "r9w",
+            Register::R10w => "r10w",
+            Register::R11w => "r11w",
+            Register::R12w => "r12w",
+            Register::R13w => "r13w",
+            Register::R14w => "r14w",
+            Register::R15w => "r15w",
+            Register::Al => "al",
+            Register::Cl => "cl",
+            Register::Dl => "dl",
+            Register::Bl => "bl",
+            Register::Spl => "spl",
+            Register::Bpl => "bpl",
+            Register::Sil => "sil",
+            Register::Dil => "dil",
+            Register::R8b => "r8b",
+            Register::R9b => "r9b",
+            Register::R10b => "r10b",
+            Register::R11b => "r11b",
+            Register::R12b => "r12b",
+            Register::R13b => "r13b",
+            Register::R14b => "r14b",
+            Register::R15b => "r15b",
         }
         .into()
     }
@@ -0,0 +1,48 @@
+use super::AsAsm;
+
+/// Size of an operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    /// 8 bits
+    Byte,
+    /// 16 bits
+    Word,
+    /// 32 bits
+    Dword,
+    /// 64 bits
+    Qword,
+}
+
+impl Size {
+    /// The number of bytes of this size.
+    pub fn bytes(&self) -> u8 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+            Size::Qword => 8,
+        }
+    }
+
+    /// The data directive declaring values of this size, e.g. `db` for bytes.
+    pub fn directive(&self) -> &'static str {
+        match self {
+            Size::Byte => "db",
+            Size::Word => "dw",
+            Size::Dword => "dd",
+            Size::Qword => "dq",
+        }
+    }
+}
+
+impl AsAsm for Size {
+    fn as_asm(&self) -> String {
+        match self {
+            Size::Byte => "byte",
+            Size::Word => "word",
+            Size::Dword => "dword",
+            Size::Qword => "qword",
+        }
+        .into()
+    }
+}
@@ -0,0 +1,410 @@
+//! Linux x86-64 system calls.
+//!
+//! A [`LinuxSyscall`] is emitted with [`Program::syscall`](super::Program::syscall), which moves
+//! its arguments to the registers expected by the kernel, preceded by a comment naming it.
+//!
+//! ```
+//! # use tiny_elf::asm::{syscalls::*, Memory, Mnemonic::*, Program, Register::*};
+//! let program = Program::default()
+//!     .insert_rodata("msg", "hi\n")
+//!     .add(Mov(Rdx, 3.into()))
+//!     .syscall(LinuxSyscall::Write {
+//!         fd: STDOUT.into(),
+//!         buf: Memory::from("msg").into(),
+//!         count: Rdx.into(),
+//!     })
+//!     .syscall(LinuxSyscall::Exit { code: 0.into() });
+//! ```
+//!
+//! # See
+//!
+//! - <http://blog.rchapman.org/posts/Linux_System_Call_Table_for_x86_64/>
+//! - `man 2 syscall`
+
+use super::{Memory, Mnemonic, Operand, Register, Size};
+
+pub const STDIN: i32 = 0;
+pub const STDOUT: i32 = 1;
+pub const STDERR: i32 = 2;
+
+/// Resolves a relative path of [`LinuxSyscall::OpenAt`] from the current directory.
+pub const AT_FDCWD: i32 = -100;
+
+pub const O_RDONLY: i32 = 0o0;
+pub const O_WRONLY: i32 = 0o1;
+pub const O_RDWR: i32 = 0o2;
+pub const O_CREAT: i32 = 0o100;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+
+pub const SEEK_SET: i32 = 0;
+pub const SEEK_CUR: i32 = 1;
+pub const SEEK_END: i32 = 2;
+
+pub const PROT_NONE: i32 = 0x0;
+pub const PROT_READ: i32 = 0x1;
+pub const PROT_WRITE: i32 = 0x2;
+pub const PROT_EXEC: i32 = 0x4;
+
+pub const MAP_SHARED: i32 = 0x01;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+pub const GRND_NONBLOCK: i32 = 0x1;
+pub const GRND_RANDOM: i32 = 0x2;
+
+/// Registers holding the arguments of a system call, in order.
+///
+/// Unlike [`Register::ARGUMENTS`], the fourth one is [`R10`](Register::R10) as
+/// [`Rcx`](Register::Rcx) is overwritten by the `syscall` instruction.
+pub const ARGUMENTS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::R10,
+    Register::R8,
+    Register::R9,
+];
+
+/// A Linux system call along with its arguments.
+///
+/// Arguments are moved to their register as with [`Mnemonic::Mov`]: a memory is dereferenced
+/// unless it is an [address](Memory::is_address), e.g. `Memory::from("msg")` for a
+/// buffer. The result is returned in [`Rax`](Register::Rax), a negative value being an error number.
+#[derive(Debug, Clone)]
+pub enum LinuxSyscall {
+    /// Reads up to `count` bytes from `fd` into `buf`.
+    Read {
+        fd: Operand,
+        buf: Operand,
+        count: Operand,
+    },
+    /// Writes `count` bytes of `buf` to `fd`.
+    Write {
+        fd: Operand,
+        buf: Operand,
+        count: Operand,
+    },
+    /// Opens the null terminated `path`, e.g. with [`O_RDONLY`], returning a file descriptor.
+    Open {
+        path: Operand,
+        flags: Operand,
+        mode: Operand,
+    },
+    Close {
+        fd: Operand,
+    },
+    /// Moves the offset of `fd`, `whence` being [`SEEK_SET`], [`SEEK_CUR`] or [`SEEK_END`].
+    Lseek {
+        fd: Operand,
+        offset: Operand,
+        whence: Operand,
+    },
+    /// Maps memory, e.g. anonymous pages with [`MAP_PRIVATE`] | [`MAP_ANONYMOUS`] and a `fd` of
+    /// `-1`.
+    Mmap {
+        addr: Operand,
+        len: Operand,
+        prot: Operand,
+        flags: Operand,
+        fd: Operand,
+        offset: Operand,
+    },
+    Munmap {
+        addr: Operand,
+        len: Operand,
+    },
+    /// Sets the end of the heap, returning the new one. An `addr` of `0` gives the current end.
+    Brk {
+        addr: Operand,
+    },
+    /// Exits the current thread.
+    Exit {
+        code: Operand,
+    },
+    /// Exits every thread of the process.
+    ExitGroup {
+        code: Operand,
+    },
+    /// Writes the time of `clock`, e.g. [`CLOCK_MONOTONIC`], in the `timespec` pointed to by
+    /// `tp`.
+    ClockGettime {
+        clock: Operand,
+        tp: Operand,
+    },
+    /// Opens the null terminated `path`, relative to `dirfd` or [`AT_FDCWD`].
+    OpenAt {
+        dirfd: Operand,
+        path: Operand,
+        flags: Operand,
+        mode: Operand,
+    },
+    /// Fills `buf` with `len` random bytes.
+    GetRandom {
+        buf: Operand,
+        len: Operand,
+        flags: Operand,
+    },
+}
+
+impl LinuxSyscall {
+    /// The number identifying this system call, passed in [`Rax`](Register::Rax).
+    pub fn number(&self) -> u32 {
+        match self {
+            LinuxSyscall::Read { .. } => 0,
+            LinuxSyscall::Write { .. } => 1,
+            LinuxSyscall::Open { .. } => 2,
+            LinuxSyscall::Close { .. } => 3,
+            LinuxSyscall::Lseek { .. } => 8,
+            LinuxSyscall::Mmap { .. } => 9,
+            LinuxSyscall::Munmap { .. } => 11,
+            LinuxSyscall::Brk { .. } => 12,
+            LinuxSyscall::Exit { .. } => 60,
+            LinuxSyscall::ClockGettime { .. } => 228,
+            LinuxSyscall::ExitGroup { .. } => 231,
+            LinuxSyscall::OpenAt { .. } => 257,
+            LinuxSyscall::GetRandom { .. } => 318,
+        }
+    }
+
+    /// The name of this system call, as in its man page.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinuxSyscall::Read { .. } => "read",
+            LinuxSyscall::Write { .. } => "write",
+            LinuxSyscall::Open { .. } => "open",
+            LinuxSyscall::Close { .. } => "close",
+            LinuxSyscall::Lseek { .. } => "lseek",
+            LinuxSyscall::Mmap { .. } => "mmap",
+            LinuxSyscall::Munmap { .. } => "munmap",
+            LinuxSyscall::Brk { .. } => "brk",
+            LinuxSyscall::Exit { .. } => "exit",
+            LinuxSyscall::ClockGettime { .. } => "clock_gettime",
+            LinuxSyscall::ExitGroup { .. } => "exit_group",
+            LinuxSyscall::OpenAt { .. } => "openat",
+            LinuxSyscall::GetRandom { .. } => "getrandom",
+        }
+    }
+
+    /// The arguments of this system call, in order.
+    pub fn arguments(&self) -> Vec<&Operand> {
+        match self {
+            LinuxSyscall::Read { fd, buf, count } | LinuxSyscall::Write { fd, buf, count } => {
+                vec![fd, buf, count]
+            }
+            LinuxSyscall::Open { path, flags, mode } => vec![path, flags, mode],
+            LinuxSyscall::Close { fd } => vec![fd],
+            LinuxSyscall::Lseek { fd, offset, whence } => vec![fd, offset, whence],
+            LinuxSyscall::Mmap {
+                addr,
+                len,
+                prot,
+                flags,
+                fd,
+                offset,
+            } => vec![addr, len, prot, flags, fd, offset],
+            LinuxSyscall::Munmap { addr, len } => vec![addr, len],
+            LinuxSyscall::Brk { addr } => vec![addr],
+            LinuxSyscall::Exit { code } | LinuxSyscall::ExitGroup { code } => vec![code],
+            LinuxSyscall::ClockGettime { clock, tp } => vec![clock, tp],
+            LinuxSyscall::OpenAt {
+                dirfd,
+                path,
+                flags,
+                mode,
+            } => vec![dirfd, path, flags, mode],
+            LinuxSyscall::GetRandom { buf, len, flags } => vec![buf, len, flags],
+        }
+    }
+
+    /// The instructions performing this system call: a comment naming it, the moves of its
+    /// arguments, then the `syscall` itself.
+    ///
+    /// Arguments are moved as if simultaneously: a register read by an argument, e.g. as the
+    /// base of a memory, is only overwritten once read. Arguments already in their register are
+    /// not moved, and the number is moved last so that an argument can be held in
+    /// [`Rax`](Register::Rax).
+    pub fn instructions(&self) -> Vec<Mnemonic> {
+        let mut instructions = vec![Mnemonic::Comment(self.name().into())];
+
+        let arguments = self.arguments();
+        let mut pending: Vec<_> = ARGUMENTS
+            .into_iter()
+            .zip(arguments.iter().map(|arg| (*arg).clone()))
+            .filter(|(target, arg)| !matches!(arg, Operand::Reg(r) if r == target))
+            .collect();
+        // callee-saved registers used as scratch, kept below the stack pointer
+        let mut saved = Vec::new();
+
+        while !pending.is_empty() {
+            let free = (0..pending.len()).find(|&i| {
+                let target = pending[i].0;
+                pending
+                    .iter()
+                    .enumerate()
+                    .all(|(j, (_, arg))| i == j || !reads(arg).contains(&target))
+            });
+            if let Some(i) = free {
+                let (target, arg) = pending.remove(i);
+                instructions.extend(load(target, &arg));
+                continue;
+            }
+
+            // every target is read by another argument: copy one to a scratch register
+            let read: Vec<_> = pending.iter().flat_map(|(_, arg)| reads(arg)).collect();
+            let scratch = [Register::R11, Register::Rcx, Register::Rax]
+                .into_iter()
+                .chain(ARGUMENTS[arguments.len()..].iter().copied())
+                .find(|r| !read.contains(r));
+            let scratch = scratch.unwrap_or_else(|| {
+                // only when every caller-saved one is read, leaving a callee-saved one unread
+                let r = [Register::Rbx, Register::R12, Register::R13]
+                    .into_iter()
+                    .chain([Register::R14, Register::R15, Register::Rbp])
+                    .find(|r| !read.contains(r))
+                    .unwrap();
+                saved.push(r);
+                instructions.push(Mnemonic::Store(red_zone(saved.len()), r.into()));
+                r
+            });
+
+            let target = pending[0].0;
+            instructions.push(Mnemonic::Mov(scratch, target.into()));
+            for (_, arg) in &mut pending {
+                replace(arg, target, scratch);
+            }
+        }
+
+        for (i, r) in saved.iter().enumerate() {
+            instructions.push(Mnemonic::Mov(*r, red_zone(i + 1).into()));
+        }
+
+        instructions.push(Mnemonic::Mov(Register::Rax, (self.number() as i32).into()));
+        instructions.push(Mnemonic::Syscall);
+        instructions
+    }
+}
+
+/// The 64 bits registers read by `o`.
+fn reads(o: &Operand) -> Vec<Register> {
+    match o {
+        Operand::Reg(r) => vec![r.with_size(Size::Qword)],
+        Operand::Mem(mem) => mem.registers(),
+        Operand::Imm(_) | Operand::Expr(_) => Vec::new(),
+    }
+}
+
+/// Makes `o` read `to` instead of the 64 bits register `from`.
+fn replace(o: &mut Operand, from: Register, to: Register) {
+    match o {
+        Operand::Reg(r) if r.with_size(Size::Qword) == from => *r = to.with_size(r.size()),
+        Operand::Mem(mem) => mem.replace_register(from, to),
+        _ => {}
+    }
+}
+
+/// The `n`th slot of the red zone, the 128 bytes below the stack pointer which user code may
+/// use without moving it.
+fn red_zone(n: usize) -> Memory {
+    Memory::base(Register::Rsp)
+        .disp(-8 * n as i32)
+        .with_size(Size::Qword)
+}
+
+/// Moves `o` to the 64 bits register `target`, zero-extending smaller registers and memories.
+fn load(target: Register, o: &Operand) -> Option<Mnemonic> {
+    let size = match o {
+        Operand::Reg(r) if *r == target => return None,
+        Operand::Reg(r) => Some(r.size()),
+        Operand::Mem(mem) => mem.size(),
+        Operand::Imm(_) | Operand::Expr(_) => None,
+    };
+
+    Some(match size {
+        Some(Size::Byte | Size::Word) => Mnemonic::Movzx(target.with_size(Size::Dword), o.clone()),
+        Some(Size::Dword) => Mnemonic::Mov(target.with_size(Size::Dword), o.clone()),
+        _ => Mnemonic::Mov(target, o.clone()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{AsAsm, Register::*, Scale};
+
+    #[test]
+    fn instructions() {
+        let write = LinuxSyscall::Write {
+            fd: STDOUT.into(),
+            buf: Rsi.into(),
+            count: Memory::base(Rbx).with_size(Size::Dword).into(),
+        };
+
+        let asm: Vec<_> = write.instructions().iter().map(AsAsm::as_asm).collect();
+        assert_eq!(
+            asm,
+            [
+                "; write",
+                "mov rdi, 1",
+                "mov edx, dword [rbx]",
+                "mov rax, 1",
+                "syscall"
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_base() {
+        let write = LinuxSyscall::Write {
+            fd: STDOUT.into(),
+            buf: Rsi.into(),
+            count: Memory::base(Rdi).with_size(Size::Qword).into(),
+        };
+
+        let asm: Vec<_> = write.instructions().iter().map(AsAsm::as_asm).collect();
+        // rdi read before being overwritten
+        assert_eq!(asm[1..3], ["mov rdx, qword [rdi]", "mov rdi, 1"]);
+    }
+
+    #[test]
+    fn swap() {
+        let write = LinuxSyscall::Write {
+            fd: Rsi.into(),
+            buf: Rdi.into(),
+            count: 3.into(),
+        };
+
+        let asm: Vec<_> = write.instructions().iter().map(AsAsm::as_asm).collect();
+        assert_eq!(
+            asm[1..5],
+            ["mov rdx, 3", "mov r11, rdi", "mov rdi, rsi", "mov rsi, r11"]
+        );
+    }
+
+    #[test]
+    fn red_zone() {
+        // arguments read each other in a cycle, along with rax, rcx and r11
+        let mmap = LinuxSyscall::Mmap {
+            addr: Memory::base(Rsi).index(Rax, Scale::One).into(),
+            len: Memory::base(Rdx).index(Rcx, Scale::One).into(),
+            prot: Memory::base(R10).index(R11, Scale::One).into(),
+            flags: R8.into(),
+            fd: R9.into(),
+            offset: Rdi.into(),
+        };
+
+        let asm: Vec<_> = mmap.instructions().iter().map(AsAsm::as_asm).collect();
+        assert_eq!(
+            asm[1..4],
+            [
+                "mov qword [rsp - 8], rbx",
+                "mov rbx, rdi",
+                "mov rdi, [rsi + rax*1]"
+            ]
+        );
+        assert_eq!(asm[asm.len() - 3], "mov rbx, qword [rsp - 8]");
+    }
+}
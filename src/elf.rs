@@ -1,11 +1,18 @@
 use crate::{
     elf_header::{ElfHeader, VADDR_START},
-    patchable::Patchable,
+    patchable::{Patchable, Segment},
     prelude::*,
     program_header::{Flags, ProgramHeader},
 };
 
-pub struct Elf<'a, T>
+mod error;
+
+pub use error::*;
+
+/// Size of a memory page, segments never share one so that each keeps its own flags.
+const PAGE_SIZE: u64 = 0x1000;
+
+pub struct Elf<T>
 where
     T: AsBytes,
 {
@@ -13,10 +20,16 @@ where
     program_header: ProgramHeader,
     program: T,
     headers: Vec<ProgramHeader>,
-    datas: Vec<&'a dyn AsBytes>,
+    datas: Vec<Segment>,
+    /// Number of leading data segments brought by the program, see [`Patchable::segments`].
+    program_segments: usize,
+    /// Alignment of the program start address, see [`Patchable::alignment`].
+    program_align: u32,
+    /// Whether the program is resolved for the current layout, see [`Elf::encode`].
+    backpatched: bool,
 }
 
-impl<'a, T> Elf<'a, T>
+impl<T> Elf<T>
 where
     T: AsBytes,
 {
@@ -34,6 +47,9 @@ where
             program,
             headers: Vec::new(),
             datas: Vec::new(),
+            program_segments: 0,
+            program_align: 1,
+            backpatched: false,
         };
 
         this.elf_header.increment_pheader();
@@ -42,27 +58,69 @@ where
     }
 
     /// Adds binary data to the file.
-    pub fn add_data(&mut self, data: &'a dyn AsBytes, flags: Flags) {
-        self.elf_header.increment_pheader();
+    pub fn add_data(&mut self, data: &dyn AsBytes, flags: Flags) {
+        let segment = Segment {
+            data: data.as_bytes(),
+            flags,
+            align: 1,
+        };
+        self.insert_data(self.datas.len(), segment);
+        // the layout changed
+        self.backpatched = false;
+    }
 
-        let mut header = ProgramHeader::from_data(&data.as_bytes(), flags);
+    fn insert_data(&mut self, index: usize, segment: Segment) {
+        self.elf_header.increment_pheader();
 
-        let previous_data_len =
-            self.program.as_bytes().len() + self.datas.iter().flat_map(|d| d.as_bytes()).count();
+        self.headers.insert(
+            index,
+            ProgramHeader::from_data(&segment.data, segment.flags),
+        );
+        self.datas.insert(index, segment);
 
-        header.set_offset(previous_data_len as u64);
-        header.set_addr(VADDR_START + previous_data_len as u64);
-        self.headers.push(header);
+        self.update_headers();
+    }
 
-        self.datas.push(data);
+    /// Updates the size and position of every segment, e.g. after the program size changed.
+    ///
+    /// The first segment maps the headers along the program. Following segments are packed in
+    /// the file but each start on a new page in memory, keeping their address and offset
+    /// congruent modulo the page size. Segments are padded with zeros to respect their
+    /// alignment.
+    fn update_headers(&mut self) {
+        let program_offset = self.program_offset() as u64;
+        self.elf_header.set_entry(VADDR_START + program_offset);
+
+        let program_end = program_offset + self.program.as_bytes().len() as u64;
+        self.program_header.set_size(program_end);
+
+        let mut offset = program_end;
+        let mut end_addr = VADDR_START + program_end;
+        for (header, segment) in self.headers.iter_mut().zip(&self.datas) {
+            offset = offset.next_multiple_of(segment.align as u64);
+            let addr = end_addr.next_multiple_of(PAGE_SIZE) + offset % PAGE_SIZE;
+
+            header.set_offset(offset);
+            header.set_addr(addr);
+
+            offset += segment.data.len() as u64;
+            end_addr = addr + segment.data.len() as u64;
+        }
     }
 
     /// Effectively computes the final binary size.
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
+        match (self.headers.last(), self.datas.last()) {
+            (Some(header), Some(segment)) => header.offset() as usize + segment.data.len(),
+            _ => self.program_offset() + self.program.as_bytes().len(),
+        }
+    }
+
+    /// Offset of the program in the file, right after the headers unless it must be aligned.
+    fn program_offset(&self) -> usize {
         self.len_headers()
-            + self.program.as_bytes().len()
-            + self.datas.iter().flat_map(|d| d.as_bytes()).count()
+            .next_multiple_of(self.program_align as usize)
     }
 
     /// Size of all the headers in the file
@@ -73,21 +131,67 @@ where
     }
 }
 
-impl<'a, T> Elf<'a, T>
+impl<T> Elf<T>
 where
     T: AsBytes + Patchable,
+    T::Error: Into<ElfError>,
 {
-    pub fn backpatch(&mut self) {
-        let data_addr = self.len_headers() + self.program.as_bytes().len();
+    /// Computes the final layout of the program and resolves its addresses.
+    ///
+    /// The segments of the program (see [`Patchable::segments`]) are mapped right after it, before
+    /// any data added with [`Elf::add_data`].
+    ///
+    /// # Errors
+    ///
+    /// If the program can't be resolved, or if it is mapped above 32 bits addresses.
+    pub fn backpatch(&mut self) -> Result<(), ElfError> {
+        self.program.relax();
+        self.program_align = self.program.alignment();
+
+        if self.program_segments == 0 {
+            for (index, segment) in self.program.segments().into_iter().enumerate() {
+                self.insert_data(index, segment);
+                self.program_segments += 1;
+            }
+        }
+        self.update_headers();
 
-        self.program.backpatch(
-            self.elf_header.entry() as u32,
-            VADDR_START as u32 + data_addr as u32,
-        );
+        let address = |addr: u64| u32::try_from(addr).map_err(|_| ElfError::AddressOverflow(addr));
+        let segment_addrs = self.headers[..self.program_segments]
+            .iter()
+            .map(|header| address(header.addr()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.program
+            .backpatch(address(self.elf_header.entry())?, &segment_addrs)
+            .map_err(Into::into)?;
+
+        for (index, segment) in self.program.segments().into_iter().enumerate() {
+            self.datas[index] = segment;
+        }
+
+        self.backpatched = true;
+        Ok(())
+    }
+
+    /// The content of the file, once backpatched.
+    ///
+    /// Prefer it to [`AsBytes::as_bytes`], which gives an unresolved program, and no bytes for
+    /// instructions that can't be encoded.
+    ///
+    /// # Errors
+    ///
+    /// [`ElfError::NotBackpatched`] unless [`Elf::backpatch`] succeeded since the last change.
+    pub fn encode(&self) -> Result<Vec<u8>, ElfError> {
+        if !self.backpatched {
+            return Err(ElfError::NotBackpatched);
+        }
+
+        Ok(self.as_bytes())
     }
 }
 
-impl<'a, T> AsBytes for Elf<'a, T>
+impl<T> AsBytes for Elf<T>
 where
     T: AsBytes,
 {
@@ -101,11 +205,35 @@ where
             data.append(&mut header.as_bytes());
         }
 
+        data.resize(self.program_offset(), 0);
         data.append(&mut self.program.as_bytes());
-        for d in &self.datas {
-            data.append(&mut d.as_bytes());
+
+        for (header, segment) in self.headers.iter().zip(&self.datas) {
+            data.resize(header.offset() as usize, 0);
+            data.extend_from_slice(&segment.data);
         }
 
         data
     }
 }
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use super::*;
+    use crate::asm::{Mnemonic::*, Program, Register::*};
+
+    #[test]
+    fn encode() {
+        let mut elf = Elf::new(Program::default().add(Add(Rax, Ebx.into())));
+        assert_eq!(elf.encode(), Err(ElfError::NotBackpatched));
+        assert!(matches!(elf.backpatch(), Err(ElfError::Asm(_))));
+        assert_eq!(elf.encode(), Err(ElfError::NotBackpatched));
+
+        let mut elf = Elf::new(Program::default().add(Ret));
+        elf.backpatch().unwrap();
+        assert_eq!(elf.encode(), Ok(elf.as_bytes()));
+
+        elf.add_data(&0u8, Flags::Readable);
+        assert_eq!(elf.encode(), Err(ElfError::NotBackpatched));
+    }
+}
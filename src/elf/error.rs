@@ -0,0 +1,50 @@
+use std::{error::Error, fmt::Display};
+
+#[cfg(feature = "asm")]
+use crate::asm::AsmError;
+
+/// An error encountered while building an [`Elf`](super::Elf).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// The program could not be assembled.
+    #[cfg(feature = "asm")]
+    Asm(AsmError),
+    /// A segment is mapped above the 32 bits addresses a [`Patchable`] is resolved with.
+    ///
+    /// [`Patchable`]: crate::patchable::Patchable
+    AddressOverflow(u64),
+    /// The file is written before its program is backpatched, see [`Elf::encode`].
+    ///
+    /// [`Elf::encode`]: super::Elf::encode
+    NotBackpatched,
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "asm")]
+            ElfError::Asm(e) => e.fmt(f),
+            ElfError::AddressOverflow(addr) => {
+                write!(f, "Address {addr:#x} does not fit in 32 bits")
+            }
+            ElfError::NotBackpatched => write!(f, "The program must be backpatched first"),
+        }
+    }
+}
+
+impl Error for ElfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "asm")]
+            ElfError::Asm(e) => Some(e),
+            ElfError::AddressOverflow(_) | ElfError::NotBackpatched => None,
+        }
+    }
+}
+
+#[cfg(feature = "asm")]
+impl From<AsmError> for ElfError {
+    fn from(e: AsmError) -> Self {
+        ElfError::Asm(e)
+    }
+}
@@ -71,6 +71,12 @@ impl ElfHeader {
     pub fn entry(&self) -> u64 {
         self.entry
     }
+
+    /// Sets the entrypoint address of the file, when the program doesn't directly follow the
+    /// headers.
+    pub fn set_entry(&mut self, entry: u64) {
+        self.entry = entry;
+    }
 }
 
 impl AsBytes for ElfHeader {
@@ -1,62 +1,74 @@
 #[cfg(feature = "asm")]
-fn main() -> std::io::Result<()> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     use std::{env, fs::File, io::prelude::Write};
 
     use tiny_elf::{
-        asm::{AsAsm, Program},
+        asm::{AsAsm, Expression, Program},
         prelude::*,
-        program_header::Flags,
     };
 
     let word = env::args()
         .nth(1)
         .unwrap_or("Hello World, this is my tiny executable\n".into());
-    let word_len = word.len() as i32;
     let upward_data = "We went upward\n";
 
     let program = {
-        use tiny_elf::asm::{Memory, Mnemonic::*, Register::*};
+        use tiny_elf::asm::{
+            syscalls::{LinuxSyscall, STDIN, STDOUT},
+            Memory,
+            Mnemonic::*,
+            Register::*,
+        };
 
         Program::default()
+            .minimal_immediates()
             .add(Mov(Rax, 8.into()))
             .add(Mov(Rbx, 2.into()))
+            .add(Cqo)
             .add(IDiv(Rbx))
             .add(Mov(Rdx, 4.into()))
             .add(Cmp(Rax, Rdx.into()))
             .add(Je("foo".into()))
             .label("upward")
-            .insert_data("upward_data", upward_data)
-            .add(Mov(Rsi, Memory::from("upward_data").into()))
-            .add(Mov(Rdx, (upward_data.len() as i32).into()))
+            .insert_rodata("upward_data", upward_data)
+            .insert_rodata("upward_data_end", "")
+            .constant(
+                "UPWARD_LEN",
+                Expression::from("upward_data_end") - "upward_data",
+            )
+            .add(Lea(Rsi, "upward_data".into()))
+            .add(Mov(Rdx, Expression::from("UPWARD_LEN").into()))
             .add(Call("print".into()))
             .add(Jmp("exit".into()))
             .label("read")
-            .add(Mov(Rax, 0.into()))
-            .add(Mov(Rdi, 0.into()))
-            .add(Mov(Rsi, Memory::from("msg").into()))
-            .add(Mov(Rdx, word_len.into()))
-            .add(Syscall)
+            .syscall(LinuxSyscall::Read {
+                fd: STDIN.into(),
+                buf: Memory::from("msg").into(),
+                count: Expression::from("MSG_LEN").into(),
+            })
             .label("foo")
             .add(Mov(Rsi, Memory::from("msg").into()))
-            .add(Mov(Rdx, word_len.into()))
+            .add(Mov(Rdx, Expression::from("MSG_LEN").into()))
             .add(Call("print".into()))
             .add(Jmp("upward".into()))
             // functions
             .func("print")
-            .add(Mov(Rax, 1.into()))
-            .add(Mov(Rdi, 1.into()))
-            .add(Syscall)
+            .syscall(LinuxSyscall::Write {
+                fd: STDOUT.into(),
+                buf: Rsi.into(),
+                count: Rdx.into(),
+            })
             .func_end()
             .label("exit")
-            .add(Mov(Rax, 60.into()))
-            .add(Mov(Rdi, 0.into()))
-            .add(Syscall)
+            .syscall(LinuxSyscall::Exit { code: 0.into() })
     };
-    let program = program.insert_data("msg", &word);
+    let program = program
+        .insert_data("msg", word)
+        .insert_data("msg_end", "")
+        .constant("MSG_LEN", Expression::from("msg_end") - "msg");
 
     let mut elf = Elf::new(program.clone());
-    elf.add_data(program.data(), Flags::all());
-    elf.backpatch();
+    elf.backpatch()?;
 
     {
         let mut file = File::create("dump.asm").unwrap();
@@ -65,7 +77,7 @@ fn main() -> std::io::Result<()> {
 
     {
         let mut file = File::create(env::args().nth(2).unwrap_or("bin".into()))?;
-        file.write_all(&elf.as_bytes())?;
+        file.write_all(&elf.encode()?)?;
     }
 
     Ok(())
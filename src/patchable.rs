@@ -1,3 +1,41 @@
+use crate::program_header::Flags;
+
 pub trait Patchable {
-    fn backpatch(&mut self, start_addr: u32, data_addr: u32);
+    /// The error reported when this patchable can't be resolved, e.g. an undefined label.
+    type Error;
+
+    /// Finalizes the size of this patchable, before any address is computed.
+    ///
+    /// Does nothing by default.
+    fn relax(&mut self) {}
+
+    /// Resolves the addresses of this patchable, loaded at `start_addr`.
+    ///
+    /// `segment_addrs` holds the address of each of its [segments](Patchable::segments).
+    fn backpatch(&mut self, start_addr: u32, segment_addrs: &[u32]) -> Result<(), Self::Error>;
+
+    /// The alignment `start_addr` must respect.
+    ///
+    /// Not aligned by default.
+    fn alignment(&self) -> u32 {
+        1
+    }
+
+    /// Segments brought along by this patchable, such as its data.
+    ///
+    /// They are mapped after it, each on its own pages. Their size must not change during
+    /// backpatch, only their content.
+    ///
+    /// None by default.
+    fn segments(&self) -> Vec<Segment> {
+        Vec::new()
+    }
+}
+
+/// A segment of a [`Patchable`], see [`Patchable::segments`].
+pub struct Segment {
+    pub data: Vec<u8>,
+    pub flags: Flags,
+    /// The alignment the address of this segment must respect.
+    pub align: u32,
 }
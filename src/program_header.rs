@@ -46,6 +46,19 @@ impl ProgramHeader {
         self.paddr = addr;
     }
 
+    pub fn addr(&self) -> u64 {
+        self.vaddr
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.filesz = size;
+        self.memsz = size;
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -3,6 +3,7 @@ use bitflags::bitflags;
 use crate::prelude::AsBytes;
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct Flags:u32 {
         const Executable = 0x1;
         const Writeable  = 0x2;